@@ -0,0 +1,279 @@
+use rand::Rng;
+
+use colors;
+use fov::{can_see, player_can_see};
+use game::{Game, MessageLog, PLAYER};
+use map::{is_blocked, Map};
+use object::{move_by, mut_two, Object};
+use pathfinding::{monster_path, safety_map, DIRECTIONS};
+use ranged::fire;
+use status::StatusKind;
+
+// How many turns a monster that's lost the player looks around where it last
+// saw them before giving up, and how far from there it wanders meanwhile.
+pub const SEARCH_TURNS: i32 = 10;
+pub const SEARCH_RADIUS: i32 = 4;
+// How much hp a fleeing monster gets back each turn it's out of the player's
+// sight.
+pub const FLEE_RECOVERY: i32 = 1;
+// How close a monster that shoots lets the player come before backing off.
+pub const KEEP_DISTANCE: f32 = 3.0;
+
+// What a monster knows of the player. Monsters start out `Basic`, knowing
+// nothing, and go back to it once they give up the search.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Ai {
+  Basic,
+  // after the player, who was last seen at `last_seen`
+  Hunting { last_seen: (i32, i32) },
+  // got to where the player was last seen and didn't find them there
+  Searching { last_seen: (i32, i32), turns_left: i32 },
+  // too hurt to fight, and running from where the player was last seen
+  Fleeing { last_seen: (i32, i32) },
+}
+
+pub fn move_towards(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut [Object]) {
+  let dx = target_x - objects[id].x;
+  let dy = target_y - objects[id].y;
+  let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
+
+  let dx = (dx as f32 / distance).round() as i32;
+  let dy = (dy as f32 / distance).round() as i32;
+  move_by(id, dx, dy, map, objects);
+}
+
+// Takes one step along the shortest way round to the target, or straight at it
+// if there's no way through within the search limit.
+pub fn move_along_path(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut [Object]) {
+  let (x, y) = objects[id].pos();
+  match monster_path((x, y), (target_x, target_y), map, objects).and_then(|path| path.first().cloned()) {
+    Some((next_x, next_y)) => move_by(id, next_x - x, next_y - y, map, objects),
+    None => move_towards(id, target_x, target_y, map, objects),
+  }
+}
+
+pub fn ai_take_turn(monster_id: usize, objects: &mut [Object], game: &mut Game) {
+  use self::Ai::*;
+  if let Some(ai) = objects[monster_id].ai.take() {
+    let new_ai = if objects[monster_id].has_status(StatusKind::Confused) {
+      ai_confused(monster_id, objects, game, ai)
+    } else {
+      match ai {
+        Basic | Hunting { .. } | Searching { .. } => ai_basic(monster_id, objects, game, ai),
+        Fleeing { last_seen } => ai_fleeing(monster_id, objects, game, last_seen),
+      }
+    };
+    objects[monster_id].ai = Some(new_ai);
+  }
+}
+
+// Whether the monster knows where the player is this turn: it can see them, or
+// they're right next to it, which even a blind monster notices.
+pub fn sees_player(monster: &Object, player: &Object, map: &Map) -> bool {
+  let vision = monster.fighter.map_or(0, |fighter| fighter.vision);
+  monster.distance_to(player) < 2.0 || can_see(map, monster.pos(), player.pos(), vision)
+}
+
+// Whether the monster is hurt badly enough to run, going by its type's
+// `flee_below`.
+fn is_afraid(monster: &Object) -> bool {
+  monster.fighter.is_some_and(|f| f.flee_below > 0 && f.hp * 100 < f.max_hp * f.flee_below)
+}
+
+// A fleeing monster comes back to fight once it has twice the hp it ran at.
+fn has_rallied(monster: &Object) -> bool {
+  monster.fighter.is_none_or(|f| f.hp * 100 >= f.max_hp * (2 * f.flee_below).min(100))
+}
+
+fn ai_basic(monster_id: usize, objects: &mut [Object], game: &mut Game, ai: Ai) -> Ai {
+  let (monster_x, monster_y) = objects[monster_id].pos();
+  let sees_player = sees_player(&objects[monster_id], &objects[PLAYER], &game.map);
+  // only a monster that knows the player is about has anything to run from
+  if (sees_player || ai != Ai::Basic) && is_afraid(&objects[monster_id]) {
+    let last_seen = match ai {
+      Ai::Hunting { last_seen } | Ai::Searching { last_seen, .. } if !sees_player => last_seen,
+      _ => objects[PLAYER].pos(),
+    };
+    if player_can_see(&game.map, objects[PLAYER].pos(), (monster_x, monster_y)) {
+      game.log.add(format!("The {} flees in terror!", objects[monster_id].name), colors::ORANGE);
+    }
+    return ai_fleeing(monster_id, objects, game, last_seen);
+  }
+
+  if sees_player {
+    if objects[monster_id].fighter.and_then(|f| f.ranged).is_some() {
+      ai_ranged(monster_id, objects, game);
+    } else if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
+      let (player_x, player_y) = objects[PLAYER].pos();
+      move_along_path(monster_id, player_x, player_y, &game.map, objects);
+    } else if objects[PLAYER].fighter.is_some_and(|f| f.hp > 0) {
+      let (monster, player) = mut_two(monster_id, PLAYER, objects);
+      monster.attack(player, game);
+    }
+    return Ai::Hunting { last_seen: objects[PLAYER].pos() };
+  }
+
+  match ai {
+    Ai::Basic => Ai::Basic,
+    Ai::Hunting { last_seen } => {
+      move_along_path(monster_id, last_seen.0, last_seen.1, &game.map, objects);
+      // there, or stuck on the way, so the player could be anywhere about
+      let position = objects[monster_id].pos();
+      if position == last_seen || position == (monster_x, monster_y) {
+        Ai::Searching { last_seen, turns_left: SEARCH_TURNS }
+      } else {
+        Ai::Hunting { last_seen }
+      }
+    }
+    Ai::Searching { last_seen, turns_left } => {
+      let (dx, dy) = (game.rng.gen_range(-1, 2), game.rng.gen_range(-1, 2));
+      let (x, y) = (monster_x + dx, monster_y + dy);
+      if (x - last_seen.0).abs() <= SEARCH_RADIUS && (y - last_seen.1).abs() <= SEARCH_RADIUS {
+        move_by(monster_id, dx, dy, &game.map, objects);
+      }
+      if turns_left > 1 {
+        Ai::Searching { last_seen, turns_left: turns_left - 1 }
+      } else {
+        Ai::Basic
+      }
+    }
+    Ai::Fleeing { last_seen } => Ai::Fleeing { last_seen },
+  }
+}
+
+// Heads downhill on the safety map away from where the player was last seen,
+// getting its breath back while they're out of sight, until it's well enough
+// to fight again. With nowhere better to go it's cornered, and fights back.
+fn ai_fleeing(monster_id: usize, objects: &mut [Object], game: &mut Game, last_seen: (i32, i32)) -> Ai {
+  let sees_player = sees_player(&objects[monster_id], &objects[PLAYER], &game.map);
+  let last_seen = if sees_player { objects[PLAYER].pos() } else { last_seen };
+  if !sees_player {
+    objects[monster_id].heal(FLEE_RECOVERY, game);
+  }
+  if has_rallied(&objects[monster_id]) {
+    if player_can_see(&game.map, objects[PLAYER].pos(), objects[monster_id].pos()) {
+      game.log.add(format!("The {} regains its courage.", objects[monster_id].name), colors::LIGHT_GREY);
+    }
+    return ai_basic(monster_id, objects, game, Ai::Hunting { last_seen });
+  }
+
+  if !step_to_safety(monster_id, last_seen, objects, game) && sees_player {
+    attack_player(monster_id, objects, game);
+  }
+  Ai::Fleeing { last_seen }
+}
+
+// Takes a step downhill on the safety map away from `danger`, if there's one
+// to take. Says whether it moved.
+fn step_to_safety(monster_id: usize, danger: (i32, i32), objects: &mut [Object], game: &Game) -> bool {
+  let safety = safety_map(danger, &game.map);
+  let (x, y) = objects[monster_id].pos();
+  let here = safety.get(x, y).unwrap_or(i32::MAX);
+  let step = DIRECTIONS.iter()
+    .filter_map(|&(dx, dy)| safety.get(x + dx, y + dy).map(|value| (value, (dx, dy))))
+    .filter(|&(value, (dx, dy))| value < here && !is_blocked(x + dx, y + dy, &game.map, objects))
+    .min();
+  match step {
+    Some((_, (dx, dy))) => {
+      move_by(monster_id, dx, dy, &game.map, objects);
+      true
+    }
+    None => false,
+  }
+}
+
+// Backs off if the player has come too close, even from right next to them,
+// and otherwise attacks if it can from where it is or closes in until it can.
+// Only a cornered one ends up fighting hand to hand.
+fn ai_ranged(monster_id: usize, objects: &mut [Object], game: &mut Game) {
+  let player_pos = objects[PLAYER].pos();
+  if objects[monster_id].distance_to(&objects[PLAYER]) < KEEP_DISTANCE &&
+    step_to_safety(monster_id, player_pos, objects, game) {
+    return;
+  }
+  if !attack_player(monster_id, objects, game) {
+    move_along_path(monster_id, player_pos.0, player_pos.1, &game.map, objects);
+  }
+}
+
+// Hits the player if they're right next to it, or else shoots them if it has
+// a ranged attack and a clear line to them in range. Says whether it attacked.
+fn attack_player(monster_id: usize, objects: &mut [Object], game: &mut Game) -> bool {
+  if objects[PLAYER].fighter.is_none_or(|f| f.hp <= 0) {
+    return false;
+  }
+  let player_pos = objects[PLAYER].pos();
+  if objects[monster_id].distance_to(&objects[PLAYER]) < 2.0 {
+    let (monster, player) = mut_two(monster_id, PLAYER, objects);
+    monster.attack(player, game);
+    return true;
+  }
+  match objects[monster_id].fighter.and_then(|f| f.ranged) {
+    Some(ranged) if can_see(&game.map, objects[monster_id].pos(), player_pos, ranged.range) => {
+      fire(monster_id, player_pos, objects, game);
+      true
+    }
+    _ => false,
+  }
+}
+
+
+// Stumbles about at random until the confusion wears off, then carries on as before.
+fn ai_confused(monster_id: usize, objects: &mut [Object], game: &mut Game, ai: Ai) -> Ai {
+  let (dx, dy) = (game.rng.gen_range(-1, 2), game.rng.gen_range(-1, 2));
+  move_by(monster_id, dx, dy, &game.map, objects);
+  ai
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::{self, open_level};
+
+  fn monster(x: i32, y: i32, hp: i32, flee_below: i32) -> Object {
+    let mut monster = testing::monster(x, y, 3);
+    {
+      let fighter = monster.fighter.as_mut().unwrap();
+      fighter.max_hp = 100;
+      fighter.hp = hp;
+      fighter.flee_below = flee_below;
+    }
+    monster
+  }
+
+  #[test]
+  fn monsters_are_afraid_below_flee_below() {
+    // 0 never runs, however hurt
+    assert!(!is_afraid(&monster(0, 0, 1, 0)));
+    assert!(!is_afraid(&monster(0, 0, 50, 50)));
+    assert!(is_afraid(&monster(0, 0, 49, 50)));
+    // 100 runs at the first scratch
+    assert!(!is_afraid(&monster(0, 0, 100, 100)));
+    assert!(is_afraid(&monster(0, 0, 99, 100)));
+  }
+
+  #[test]
+  fn fleeing_monsters_rally_at_twice_flee_below() {
+    assert!(!has_rallied(&monster(0, 0, 49, 25)));
+    assert!(has_rallied(&monster(0, 0, 50, 25)));
+    // twice is capped at full health
+    assert!(!has_rallied(&monster(0, 0, 99, 75)));
+    assert!(has_rallied(&monster(0, 0, 100, 75)));
+  }
+
+  #[test]
+  fn fleeing_steps_away_from_danger_on_an_open_map() {
+    let mut state = open_level();
+    let danger = (40, 20);
+    state.objects[PLAYER].set_pos(danger.0, danger.1);
+    // from every side, whichever way it goes has to be further off
+    for &(dx, dy) in DIRECTIONS.iter() {
+      let (x, y) = (danger.0 + 2 * dx, danger.1 + 2 * dy);
+      state.objects.push(monster(x, y, 10, 50));
+      assert!(step_to_safety(1, danger, &mut state.objects, &state.game));
+      let monster = state.objects.pop().unwrap();
+      assert!(monster.distance(danger.0, danger.1) > state.objects[PLAYER].distance(x, y),
+        "from {:?} it went to {:?}", (x, y), monster.pos());
+    }
+  }
+}
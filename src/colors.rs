@@ -0,0 +1,44 @@
+// A tcod-free colour type, so the game rules can build and run without SDL.
+// The values match the constants of the same name in `tcod::colors`.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Color {
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
+}
+
+impl Color {
+  pub fn new(r: u8, g: u8, b: u8) -> Self {
+    Color { r, g, b }
+  }
+}
+
+pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+pub const DARKER_GREY: Color = Color { r: 63, g: 63, b: 63 };
+pub const DARK_GREY: Color = Color { r: 95, g: 95, b: 95 };
+pub const GREY: Color = Color { r: 127, g: 127, b: 127 };
+pub const LIGHT_GREY: Color = Color { r: 159, g: 159, b: 159 };
+pub const WHITE: Color = Color { r: 255, g: 255, b: 255 };
+
+pub const DESATURATED_GREEN: Color = Color { r: 63, g: 127, b: 63 };
+
+pub const LIGHT_RED: Color = Color { r: 255, g: 63, b: 63 };
+pub const LIGHT_YELLOW: Color = Color { r: 255, g: 255, b: 63 };
+pub const LIGHT_GREEN: Color = Color { r: 63, g: 255, b: 63 };
+pub const LIGHT_CYAN: Color = Color { r: 63, g: 255, b: 255 };
+pub const LIGHT_BLUE: Color = Color { r: 63, g: 63, b: 255 };
+pub const LIGHT_VIOLET: Color = Color { r: 159, g: 63, b: 255 };
+
+pub const RED: Color = Color { r: 255, g: 0, b: 0 };
+pub const ORANGE: Color = Color { r: 255, g: 127, b: 0 };
+pub const YELLOW: Color = Color { r: 255, g: 255, b: 0 };
+pub const GREEN: Color = Color { r: 0, g: 255, b: 0 };
+pub const CYAN: Color = Color { r: 0, g: 255, b: 255 };
+pub const VIOLET: Color = Color { r: 127, g: 0, b: 255 };
+pub const SKY: Color = Color { r: 0, g: 191, b: 255 };
+
+pub const DARK_RED: Color = Color { r: 191, g: 0, b: 0 };
+pub const DARKER_RED: Color = Color { r: 127, g: 0, b: 0 };
+pub const DARKER_GREEN: Color = Color { r: 0, g: 127, b: 0 };
+pub const DARKER_ORANGE: Color = Color { r: 127, g: 63, b: 0 };
@@ -0,0 +1,272 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use serde_json;
+
+use ai::Ai;
+use colors::Color;
+use fov::torch_radius;
+use item::{Category, Item};
+use map::{from_dungeon_level, Transition};
+use object::Equipment;
+use ranged::RangedAttack;
+use scheduler::normal_speed;
+use status::OnHit;
+
+pub const DEFINITIONS_FILE: &str = "definitions.json";
+
+// The copy of the definitions the game was built with, for when no file is
+// given (headless runs, replays) and as a starting point for new ones.
+const BUILTIN_DEFINITIONS: &str = include_str!("../definitions.json");
+
+// Everything the dungeon is stocked with: what each monster and item looks
+// like, how strong it is, and how often it turns up at each depth. Spawn
+// tables are lists of transitions, as read by `from_dungeon_level`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Definitions {
+  pub max_monsters_per_room: Vec<Transition>,
+  pub max_items_per_room: Vec<Transition>,
+  pub monsters: Vec<MonsterDef>,
+  pub items: Vec<ItemDef>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonsterDef {
+  pub name: String,
+  pub glyph: char,
+  pub color: Color,
+  pub fighter: FighterDef,
+  pub ai: Ai,
+  pub spawn: Vec<Transition>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FighterDef {
+  pub max_hp: i32,
+  pub defense: i32,
+  pub power: i32,
+  pub xp: i32,
+  #[serde(default)]
+  pub on_hit: Option<OnHit>,
+  #[serde(default = "normal_speed")]
+  pub speed: i32,
+  // how many tiles away it can see the player; 0 is blind
+  #[serde(default = "torch_radius")]
+  pub vision: i32,
+  // below what percentage of its hp it runs away; 0 never does
+  #[serde(default)]
+  pub flee_below: i32,
+  #[serde(default)]
+  pub ranged: Option<RangedAttack>,
+}
+
+fn default_weight() -> i32 {
+  1
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemDef {
+  pub name: String,
+  pub glyph: char,
+  pub color: Color,
+  pub effect: Item,
+  // how much of the player's carrying capacity each one takes up
+  #[serde(default = "default_weight")]
+  pub weight: i32,
+  // how strong its effect is: the hp a potion heals, the damage a scroll
+  // does, or how many turns confusion lasts
+  #[serde(default)]
+  pub power: i32,
+  // how far away a scroll reaches, or how wide a fireball bursts
+  #[serde(default)]
+  pub range: i32,
+  #[serde(default)]
+  pub equipment: Option<Equipment>,
+  pub spawn: Vec<Transition>,
+}
+
+impl Definitions {
+  pub fn load(path: &str) -> Result<Definitions, Box<dyn Error>> {
+    let mut json = String::new();
+    File::open(path)
+      .and_then(|mut file| file.read_to_string(&mut json))
+      .map_err(|e| format!("could not read {}: {}", path, e))?;
+    Definitions::parse(&json).map_err(|e| From::from(format!("{}: {}", path, e)))
+  }
+
+  pub fn parse(json: &str) -> Result<Definitions, Box<dyn Error>> {
+    let definitions: Definitions = serde_json::from_str(json)?;
+    definitions.validate()?;
+    Ok(definitions)
+  }
+
+  // Catches the mistakes serde can't, so a bad edit stops the game at startup
+  // instead of causing odd spawns or a panic deep in the dungeon.
+  pub fn validate(&self) -> Result<(), String> {
+    check_table("max_monsters_per_room", &self.max_monsters_per_room)?;
+    check_table("max_items_per_room", &self.max_items_per_room)?;
+    if self.monsters.is_empty() {
+      return Err("there must be at least one monster".into());
+    }
+    if self.items.is_empty() {
+      return Err("there must be at least one item".into());
+    }
+
+    let mut names = HashSet::new();
+    for monster in &self.monsters {
+      let context = format!("monster \"{}\"", monster.name);
+      if monster.name.is_empty() {
+        return Err("a monster has an empty name".into());
+      }
+      if !names.insert(monster.name.clone()) {
+        return Err(format!("{} is defined more than once", context));
+      }
+      if monster.fighter.max_hp <= 0 {
+        return Err(format!("{}: max_hp must be above 0, not {}", context, monster.fighter.max_hp));
+      }
+      if monster.fighter.defense < 0 || monster.fighter.power < 0 || monster.fighter.xp < 0 {
+        return Err(format!("{}: defense, power and xp can't be negative", context));
+      }
+      if monster.fighter.speed <= 0 {
+        return Err(format!("{}: speed must be above 0, not {}", context, monster.fighter.speed));
+      }
+      if monster.fighter.vision < 0 {
+        return Err(format!("{}: vision can't be negative, not {}", context, monster.fighter.vision));
+      }
+      if monster.fighter.flee_below < 0 || monster.fighter.flee_below > 100 {
+        return Err(format!("{}: flee_below is a percentage from 0 to 100, not {}", context, monster.fighter.flee_below));
+      }
+      if let Some(ranged) = monster.fighter.ranged {
+        if ranged.range <= 0 || ranged.power < 0 {
+          return Err(format!("{}: ranged range must be above 0 and power can't be negative", context));
+        }
+        if ranged.accuracy == 0 || ranged.accuracy > 100 {
+          return Err(format!("{}: ranged accuracy is a percentage from 1 to 100, not {}", context, ranged.accuracy));
+        }
+      }
+      if let Some(on_hit) = monster.fighter.on_hit {
+        if on_hit.turns <= 0 {
+          return Err(format!("{}: on_hit turns must be above 0, not {}", context, on_hit.turns));
+        }
+        if on_hit.chance == 0 || on_hit.chance > 100 {
+          return Err(format!("{}: on_hit chance is a percentage from 1 to 100, not {}", context, on_hit.chance));
+        }
+      }
+      check_table(&format!("{} spawn", context), &monster.spawn)?;
+    }
+
+    let mut names = HashSet::new();
+    for item in &self.items {
+      let context = format!("item \"{}\"", item.name);
+      if item.name.is_empty() {
+        return Err("an item has an empty name".into());
+      }
+      if !names.insert(item.name.clone()) {
+        return Err(format!("{} is defined more than once", context));
+      }
+      if item.weight < 0 {
+        return Err(format!("{}: weight can't be negative", context));
+      }
+      let (uses_power, uses_range) = match item.effect {
+        Item::Heal | Item::Haste | Item::Regeneration => (true, false),
+        Item::Lightning | Item::Confuse | Item::Fireball | Item::Slow => (true, true),
+        Item::Sword | Item::Shield | Item::Armor | Item::Helm | Item::Ring => (false, false),
+      };
+      if uses_power && item.power <= 0 {
+        return Err(format!("{}: {:?} items need a power above 0, not {}", context, item.effect, item.power));
+      }
+      if uses_range && item.range <= 0 {
+        return Err(format!("{}: {:?} items need a range above 0, not {}", context, item.effect, item.range));
+      }
+      if (!uses_power && item.power != 0) || (!uses_range && item.range != 0) {
+        return Err(format!("{}: {:?} items don't use power or range", context, item.effect));
+      }
+      let equips = item.effect.category() == Category::Equipment;
+      match item.equipment {
+        Some(_) if !equips => {
+          return Err(format!("{}: only Sword, Shield, Armor, Helm and Ring items can have equipment, not {:?}", context, item.effect));
+        }
+        Some(equipment) if equipment.equipped => {
+          return Err(format!("{}: equipment can't start out equipped", context));
+        }
+        None if equips => {
+          return Err(format!("{}: {:?} items need an equipment section", context, item.effect));
+        }
+        _ => {}
+      }
+      check_table(&format!("{} spawn", context), &item.spawn)?;
+    }
+
+    // spawn chances only change where some table has a transition, so those
+    // levels stand in for the whole dungeon
+    let tables = self.monsters.iter().map(|monster| &monster.spawn)
+      .chain(self.items.iter().map(|item| &item.spawn))
+      .chain(vec![&self.max_monsters_per_room, &self.max_items_per_room]);
+    let mut levels: Vec<u32> = tables.flat_map(|table| table.iter().map(|transition| transition.level)).collect();
+    levels.sort();
+    levels.dedup();
+    for level in levels {
+      if self.monsters.iter().all(|monster| from_dungeon_level(&monster.spawn, level) == 0) &&
+        from_dungeon_level(&self.max_monsters_per_room, level) > 0 {
+        return Err(format!("no monster can spawn on dungeon level {}, but rooms there get monsters", level));
+      }
+      if self.items.iter().all(|item| from_dungeon_level(&item.spawn, level) == 0) &&
+        from_dungeon_level(&self.max_items_per_room, level) > 0 {
+        return Err(format!("no item can spawn on dungeon level {}, but rooms there get items", level));
+      }
+    }
+    Ok(())
+  }
+
+  // What one of the named item weighs, or nothing for an item these
+  // definitions don't know.
+  pub fn item_weight(&self, name: &str) -> i32 {
+    self.item(name).map_or(0, |item| item.weight)
+  }
+
+  pub fn item_power(&self, name: &str) -> i32 {
+    self.item(name).map_or(0, |item| item.power)
+  }
+
+  pub fn item_range(&self, name: &str) -> i32 {
+    self.item(name).map_or(0, |item| item.range)
+  }
+
+  // A hash of everything in the definitions, to tell whether a replay was
+  // recorded with these. FNV-1a, so it's the same on every build.
+  pub fn fingerprint(&self) -> u64 {
+    let json = serde_json::to_string(self).expect("definitions always serialize");
+    json.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3))
+  }
+
+  fn item(&self, name: &str) -> Option<&ItemDef> {
+    self.items.iter().find(|item| item.name == name)
+  }
+}
+
+impl Default for Definitions {
+  fn default() -> Self {
+    Definitions::parse(BUILTIN_DEFINITIONS).expect("the built-in definitions are invalid")
+  }
+}
+
+// Transitions are looked up from the deepest down, so they have to be in order.
+fn check_table(name: &str, table: &[Transition]) -> Result<(), String> {
+  if table.is_empty() {
+    return Err(format!("{}: the table is empty", name));
+  }
+  for pair in table.windows(2) {
+    if pair[0].level >= pair[1].level {
+      return Err(format!("{}: levels must go up, but {} comes before {}", name, pair[0].level, pair[1].level));
+    }
+  }
+  if table[0].level == 0 {
+    return Err(format!("{}: dungeon levels start at 1", name));
+  }
+  Ok(())
+}
@@ -0,0 +1,172 @@
+use colors;
+use game::{Action, Event, GameState, MessageLog, PLAYER};
+use item::{carried_weight, item_weight, CARRY_CAPACITY};
+use map::{MAP_HEIGHT, MAP_WIDTH};
+use object::Stairs;
+use pathfinding::{astar, path_to_nearest, DIRECTIONS};
+
+// Why auto-explore or travel stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExploreStop {
+  // nowhere reachable is left unexplored
+  Explored,
+  // travel got where it was going
+  Arrived,
+  // there's no known way to where travel was going
+  NoPath,
+  // a monster is in view, by name
+  MonsterInView(String),
+  Hurt,
+  // something was said that the player should read
+  Message,
+  Dead,
+}
+
+// Why a walk stopped, and what happened on the step it stopped after, if it
+// took one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stopped {
+  pub reason: ExploreStop,
+  pub events: Vec<Event>,
+}
+
+impl Stopped {
+  fn before(reason: ExploreStop) -> Self {
+    Stopped { reason, events: vec![] }
+  }
+}
+
+// Takes one step of auto-explore: picks up what the player is standing on if
+// `pick_up` is set and it's worth having, or else walks a tile toward the
+// nearest unexplored place. Stops instead with a monster in view, and after
+// the step if the player got hurt or anything new was logged.
+pub fn explore_step(state: &mut GameState, pick_up: bool) -> Result<Vec<Event>, Stopped> {
+  if !state.player().alive {
+    return Err(Stopped::before(ExploreStop::Dead));
+  }
+  if let Some(name) = monsters_in_view(state).pop() {
+    state.game.log.add(format!("Not with the {} in view.", name), colors::LIGHT_GREY);
+    return Err(Stopped::before(ExploreStop::MonsterInView(name)));
+  }
+
+  let action = if pick_up && item_worth_taking(state, state.player().pos()) {
+    Action::PickUp
+  } else {
+    match explore_path(state, pick_up) {
+      Some(path) => move_toward(state, path[0]),
+      None => {
+        state.game.log.add("There's nowhere left to explore.", colors::LIGHT_GREY);
+        return Err(Stopped::before(ExploreStop::Explored));
+      }
+    }
+  };
+  watched_step(state, action)
+}
+
+// Takes one step of travelling to `to` over explored ground. Unlike exploring,
+// it sets off with monsters already in view, and only stops for ones that
+// come into view on the way.
+pub fn travel_step(state: &mut GameState, to: (i32, i32)) -> Result<Vec<Event>, Stopped> {
+  if !state.player().alive {
+    return Err(Stopped::before(ExploreStop::Dead));
+  }
+  if state.player().pos() == to {
+    return Err(Stopped::before(ExploreStop::Arrived));
+  }
+  let action = match travel_path(state, to) {
+    Some(path) => move_toward(state, path[0]),
+    None => {
+      state.game.log.add("You don't know a way there.", colors::LIGHT_GREY);
+      return Err(Stopped::before(ExploreStop::NoPath));
+    }
+  };
+  watched_step(state, action)
+}
+
+// Takes the step, then says whether that's a reason to stop: the player died
+// or got hurt, a monster came into view, or something new was logged.
+fn watched_step(state: &mut GameState, action: Action) -> Result<Vec<Event>, Stopped> {
+  let hp = player_hp(state);
+  let messages = state.game.log.added();
+  let monsters = monsters_in_view(state);
+  let events = state.step(action);
+  let picked_up = events.iter().any(|event| matches!(*event, Event::ItemPickedUp { .. }));
+
+  if !state.player().alive {
+    Err(Stopped { reason: ExploreStop::Dead, events })
+  } else if player_hp(state) < hp {
+    Err(Stopped { reason: ExploreStop::Hurt, events })
+  } else if let Some(name) = monsters_in_view(state).into_iter().find(|name| !monsters.contains(name)) {
+    state.game.log.add(format!("You see the {}.", name), colors::LIGHT_GREY);
+    Err(Stopped { reason: ExploreStop::MonsterInView(name), events })
+  } else if state.game.log.added() > messages + if picked_up { 1 } else { 0 } {
+    // picking something up says so, which is no reason to stop
+    Err(Stopped { reason: ExploreStop::Message, events })
+  } else {
+    Ok(events)
+  }
+}
+
+// The way to the nearest explored floor tile next to one that isn't, or to
+// an item that's worth picking up if `pick_up` is set. Only goes over tiles
+// the player has seen, and around anything standing in the way.
+pub fn explore_path(state: &GameState, pick_up: bool) -> Option<Vec<(i32, i32)>> {
+  let map = &state.game.map;
+  let is_frontier = |x: i32, y: i32| {
+    DIRECTIONS.iter().any(|&(dx, dy)| {
+      let (x, y) = (x + dx, y + dy);
+      x >= 0 && y >= 0 && x < MAP_WIDTH && y < MAP_HEIGHT && !map[x as usize][y as usize].explored
+    })
+  };
+
+  path_to_nearest(state.player().pos(), |x, y| {
+    is_frontier(x, y) || (pick_up && item_worth_taking(state, (x, y)))
+  }, |x, y| known_floor_cost(state, x, y))
+}
+
+// The way to `to` over tiles the player has seen, around anything standing in
+// the way.
+pub fn travel_path(state: &GameState, to: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+  let size = (MAP_WIDTH * MAP_HEIGHT) as usize;
+  astar(state.player().pos(), to, size, |x, y| known_floor_cost(state, x, y))
+}
+
+// Exploring and travelling only walk on explored floor with nothing on it
+// that blocks.
+fn known_floor_cost(state: &GameState, x: i32, y: i32) -> Option<i32> {
+  let tile = &state.game.map[x as usize][y as usize];
+  let occupied = state.objects.iter().skip(PLAYER + 1).any(|object| object.blocks && object.pos() == (x, y));
+  if tile.explored && !tile.blocked && !occupied { Some(0) } else { None }
+}
+
+// Where the stairs of the given kind are, if the player has seen them.
+pub fn known_stairs(state: &GameState, kind: Stairs) -> Option<(i32, i32)> {
+  state.objects.iter()
+    .find(|object| object.stairs == Some(kind) && state.game.map[object.x as usize][object.y as usize].explored)
+    .map(|object| object.pos())
+}
+
+fn move_toward(state: &GameState, (x, y): (i32, i32)) -> Action {
+  let player = state.player();
+  Action::Move(x - player.x, y - player.y)
+}
+
+// Whether picking up at `pos` would get the player something they can carry.
+fn item_worth_taking(state: &GameState, pos: (i32, i32)) -> bool {
+  // picking up takes the first item there, as the PickUp action does
+  match state.objects.iter().find(|object| object.pos() == pos && object.item.is_some()) {
+    Some(item) => carried_weight(&state.game) + item_weight(item, &state.game) <= CARRY_CAPACITY,
+    None => false,
+  }
+}
+
+fn monsters_in_view(state: &GameState) -> Vec<String> {
+  state.objects.iter()
+    .filter(|object| object.ai.is_some() && object.alive && state.fov.is_in_fov(object.x, object.y))
+    .map(|object| object.name.clone())
+    .collect()
+}
+
+fn player_hp(state: &GameState) -> i32 {
+  state.player().fighter.map_or(0, |fighter| fighter.hp)
+}
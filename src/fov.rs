@@ -0,0 +1,115 @@
+use map::{in_bounds, Map, MAP_WIDTH, MAP_HEIGHT};
+
+pub const FOV_LIGHT_WALLS: bool = true;
+pub const TORCH_RADIUS: i32 = 12;
+
+// the default for a serialized `vision`
+pub fn torch_radius() -> i32 {
+  TORCH_RADIUS
+}
+
+// A plain ray-casting field of view, standing in for tcod's `FovAlgorithm::Basic`
+// so that visibility can be worked out without linking libtcod.
+#[derive(Clone, Debug)]
+pub struct Fov {
+  visible: Vec<Vec<bool>>,
+}
+
+impl Fov {
+  pub fn new() -> Self {
+    Fov { visible: vec![vec![false; MAP_HEIGHT as usize]; MAP_WIDTH as usize] }
+  }
+
+  pub fn is_in_fov(&self, x: i32, y: i32) -> bool {
+    in_bounds(x, y) && self.visible[x as usize][y as usize]
+  }
+
+  pub fn compute(&mut self, map: &Map, origin_x: i32, origin_y: i32, radius: i32, light_walls: bool) {
+    for column in self.visible.iter_mut() {
+      for cell in column.iter_mut() {
+        *cell = false;
+      }
+    }
+    if !in_bounds(origin_x, origin_y) {
+      return;
+    }
+    self.visible[origin_x as usize][origin_y as usize] = true;
+
+    // cast a ray to every cell on the edge of the square around the origin
+    let mut edge = vec![];
+    for d in -radius..(radius + 1) {
+      edge.push((origin_x + d, origin_y - radius));
+      edge.push((origin_x + d, origin_y + radius));
+      edge.push((origin_x - radius, origin_y + d));
+      edge.push((origin_x + radius, origin_y + d));
+    }
+
+    for (edge_x, edge_y) in edge {
+      for (x, y) in line((origin_x, origin_y), (edge_x, edge_y)).into_iter().skip(1) {
+        if !in_bounds(x, y) || (x - origin_x).pow(2) + (y - origin_y).pow(2) > radius.pow(2) {
+          break;
+        }
+        let tile = &map[x as usize][y as usize];
+        if tile.block_sight {
+          if light_walls {
+            self.visible[x as usize][y as usize] = true;
+          }
+          break;
+        }
+        self.visible[x as usize][y as usize] = true;
+      }
+    }
+  }
+}
+
+impl Default for Fov {
+  fn default() -> Self {
+    Fov::new()
+  }
+}
+
+// Whether something at `from` that sees `radius` tiles could see `to`: it's
+// near enough and no wall is in the way. Monsters use this to look for the
+// player each with their own eyes, rather than going by what the player sees.
+pub fn can_see(map: &Map, from: (i32, i32), to: (i32, i32), radius: i32) -> bool {
+  if from == to {
+    return true;
+  }
+  if (to.0 - from.0).pow(2) + (to.1 - from.1).pow(2) > radius.pow(2) {
+    return false;
+  }
+  let points = line(from, to);
+  points[1..points.len() - 1].iter()
+    .all(|&(x, y)| in_bounds(x, y) && !map[x as usize][y as usize].block_sight)
+}
+
+// Whether the player, standing at `player`, would see what goes on at `pos`:
+// for keeping word of things they can't see out of the log.
+pub fn player_can_see(map: &Map, player: (i32, i32), pos: (i32, i32)) -> bool {
+  can_see(map, player, pos, TORCH_RADIUS)
+}
+
+// Bresenham line from `from` to `to`, both ends included.
+pub fn line(from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+  let (mut x, mut y) = from;
+  let dx = (to.0 - x).abs();
+  let dy = -(to.1 - y).abs();
+  let step_x = if x < to.0 { 1 } else { -1 };
+  let step_y = if y < to.1 { 1 } else { -1 };
+  let mut error = dx + dy;
+  let mut points = vec![(x, y)];
+
+  while (x, y) != to {
+    let doubled = 2 * error;
+    if doubled >= dy {
+      error += dy;
+      x += step_x;
+    }
+    if doubled <= dx {
+      error += dx;
+      y += step_y;
+    }
+    points.push((x, y));
+  }
+  points
+}
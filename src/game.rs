@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::mem;
+use rand::Rng;
+
+use colors::{self, Color};
+use definitions::Definitions;
+use fov::{Fov, FOV_LIGHT_WALLS, TORCH_RADIUS};
+use item::{drop_item, pick_item_up, use_item, Item};
+use map::{make_map, Map, MAP_HEIGHT, MAP_WIDTH};
+use messages::Messages;
+use object::{player_move_or_attack, DeathCallback, Fighter, Object, Slot, Stairs};
+use ranged::Projectile;
+use replay::Replay;
+use rng::GameRng;
+use scheduler::{run_until_player_ready, DROP_COST, FREE_ACTION_COST, FULL_TURN, NORMAL_SPEED, PICK_UP_COST, USE_ITEM_COST,
+  WAIT_COST};
+use stats::RunStats;
+use status::StatusKind;
+
+pub const PLAYER: usize = 0;
+pub const LEVEL_UP_BASE: i32 = 200;
+pub const LEVEL_UP_FACTOR: i32 = 150;
+
+pub trait MessageLog {
+  fn add<T: Into<String>>(&mut self, message: T, color: Color);
+}
+
+
+// A level the player has been to but isn't on right now, kept as they left it.
+#[derive(Serialize, Deserialize)]
+pub struct Level {
+  pub map: Map,
+  // everything on the level except the player
+  pub objects: Vec<Object>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Game {
+  pub map: Map,
+  pub log: Messages,
+  pub inventory: Vec<Object>,
+  pub dungeon_level: u32,
+  // every visited level other than the current one, by dungeon level
+  #[serde(default)]
+  pub levels: BTreeMap<u32, Level>,
+  // ticks of game time so far, each one a turn at normal speed
+  #[serde(default)]
+  pub turn: u64,
+  // what killed the player, once something has: the kind of thing, like "orc",
+  // and the very one, like "orc-12"
+  #[serde(default)]
+  pub cause_of_death: Option<String>,
+  #[serde(default)]
+  pub killed_by: Option<String>,
+  #[serde(default)]
+  pub stats: RunStats,
+  #[serde(default)]
+  pub rng: GameRng,
+  // None for saves from before replays were recorded, which can't be replayed
+  #[serde(default)]
+  pub replay: Option<Replay>,
+  #[serde(skip)]
+  pub events: Vec<Event>,
+  // read from the definitions file each time the game starts, not saved
+  #[serde(skip)]
+  pub defs: Definitions,
+}
+
+// Everything the player can ask for. Anything that needs a menu or a target is
+// answered by the frontend first and passed in here, so `step` never blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+  Move(i32, i32),
+  Wait,
+  PickUp,
+  Drop(usize),
+  UseItem { inventory_id: usize, target: Option<(i32, i32)> },
+  LevelUp(Stat),
+  Descend,
+  Ascend,
+}
+
+impl Action {
+  // Whether `step` can take it as it is: a move goes at most one tile each way,
+  // and a target has to be on the map. Anything else is refused unrecorded.
+  pub fn is_valid(&self) -> bool {
+    match *self {
+      Action::Move(dx, dy) => dx.abs() <= 1 && dy.abs() <= 1,
+      Action::UseItem { target: Some((x, y)), .. } => x >= 0 && y >= 0 && x < MAP_WIDTH && y < MAP_HEIGHT,
+      _ => true,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Stat {
+  Constitution,
+  Strength,
+  Agility,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+  TookTurn,
+  PlayerMoved { from: (i32, i32), to: (i32, i32) },
+  Attacked { attacker: String, target: String, damage: i32 },
+  // every tile the shot flew over, in order, ending where it stopped
+  ProjectileFired { shooter: String, path: Vec<(i32, i32)>, projectile: Projectile },
+  Died { name: String },
+  PlayerDied,
+  ItemPickedUp { name: String },
+  ItemDropped { name: String },
+  ItemUsed { item: Item },
+  Equipped { name: String, slot: Slot },
+  Unequipped { name: String, slot: Slot },
+  LevelUp { level: i32 },
+  StatusApplied { name: String, status: StatusKind },
+  StatusExpired { name: String, status: StatusKind },
+  Descended { dungeon_level: u32 },
+  Ascended { dungeon_level: u32 },
+}
+
+pub struct GameState {
+  pub objects: Vec<Object>,
+  pub game: Game,
+  pub fov: Fov,
+}
+
+impl GameState {
+  pub fn new(objects: Vec<Object>, game: Game) -> Self {
+    let mut state = GameState { objects, game, fov: Fov::new() };
+    state.recompute_fov();
+    state
+  }
+
+  pub fn player(&self) -> &Object {
+    &self.objects[PLAYER]
+  }
+
+  pub fn level_up_pending(&self) -> bool {
+    let player = self.player();
+    player.fighter.is_some_and(|f| f.xp >= level_up_xp(player.level))
+  }
+
+  pub fn stairs_under_player(&self) -> Option<Stairs> {
+    self.objects.iter()
+      .find(|object| object.pos() == self.player().pos() && object.stairs.is_some())
+      .and_then(|object| object.stairs)
+  }
+
+  // What the player's statuses turn an action into: a paralyzed player can
+  // only let the turn pass, and a confused one walks off in a random direction.
+  fn player_condition(&mut self, action: Action) -> Action {
+    let player = &self.objects[PLAYER];
+    if player.has_status(StatusKind::Paralyzed) {
+      match action {
+        Action::LevelUp(_) => action,
+        _ => {
+          self.game.log.add("You can't move!", StatusKind::Paralyzed.color());
+          Action::Wait
+        }
+      }
+    } else if player.has_status(StatusKind::Confused) {
+      match action {
+        Action::Move(dx, dy) if (dx, dy) != (0, 0) => {
+          Action::Move(self.game.rng.gen_range(-1, 2), self.game.rng.gen_range(-1, 2))
+        }
+        _ => action,
+      }
+    } else {
+      action
+    }
+  }
+
+  pub fn recompute_fov(&mut self) {
+    let (x, y) = self.objects[PLAYER].pos();
+    self.fov.compute(&self.game.map, x, y, TORCH_RADIUS, FOV_LIGHT_WALLS);
+    for (x, column) in self.game.map.iter_mut().enumerate() {
+      for (y, tile) in column.iter_mut().enumerate() {
+        if self.fov.is_in_fov(x as i32, y as i32) {
+          tile.explored = true;
+        }
+      }
+    }
+  }
+
+  // Applies one player action and, if it cost the player any energy, lets game
+  // time run until they can act again. Returns what happened along the way; an action that isn't
+  // valid does nothing at all.
+  pub fn step(&mut self, action: Action) -> Vec<Event> {
+    use self::Action::*;
+
+    if !action.is_valid() {
+      return vec![];
+    }
+    if let Some(ref mut replay) = self.game.replay {
+      replay.actions.push(action);
+    }
+
+    if self.objects[PLAYER].alive {
+      let action = self.player_condition(action);
+      // the energy the action cost, if any
+      let cost = match action {
+        Move(0, 0) | Wait => WAIT_COST,
+        Move(dx, dy) => {
+          let start = self.objects[PLAYER].pos();
+          let cost = player_move_or_attack(dx, dy, &mut self.objects, &mut self.game);
+          let end = self.objects[PLAYER].pos();
+          if start != end {
+            self.game.events.push(Event::PlayerMoved { from: start, to: end });
+          }
+          cost
+        }
+        PickUp => {
+          let player_pos = self.objects[PLAYER].pos();
+          let item_id = self.objects.iter().position(|object| {
+            object.pos() == player_pos && object.item.is_some()
+          });
+          match item_id {
+            Some(item_id) if pick_item_up(item_id, &mut self.objects, &mut self.game) => PICK_UP_COST,
+            _ => FREE_ACTION_COST,
+          }
+        }
+        Drop(inventory_id) => {
+          if inventory_id < self.game.inventory.len() {
+            drop_item(inventory_id, &mut self.objects, &mut self.game);
+            DROP_COST
+          } else {
+            FREE_ACTION_COST
+          }
+        }
+        UseItem { inventory_id, target } => {
+          if inventory_id < self.game.inventory.len() &&
+            use_item(inventory_id, target, &mut self.objects, &mut self.game, &self.fov) {
+            USE_ITEM_COST
+          } else {
+            FREE_ACTION_COST
+          }
+        }
+        LevelUp(stat) => {
+          if self.level_up_pending() {
+            level_up(stat, &mut self.objects, &mut self.game);
+          }
+          FREE_ACTION_COST
+        }
+        Descend => {
+          if self.stairs_under_player() == Some(Stairs::Down) {
+            next_level(&mut self.objects, &mut self.game);
+          }
+          FREE_ACTION_COST
+        }
+        Ascend => {
+          if self.stairs_under_player() == Some(Stairs::Up) {
+            previous_level(&mut self.objects, &mut self.game);
+          }
+          FREE_ACTION_COST
+        }
+      };
+
+      self.recompute_fov();
+
+      if cost > 0 {
+        self.game.events.push(Event::TookTurn);
+        self.objects[PLAYER].energy -= cost;
+        run_until_player_ready(&mut self.objects, &mut self.game);
+      }
+    }
+
+    mem::take(&mut self.game.events)
+  }
+}
+
+
+pub fn level_up_xp(level: i32) -> i32 {
+  LEVEL_UP_BASE + level * LEVEL_UP_FACTOR
+}
+
+// Every bit of xp the player has earned, counting what went into levelling up.
+pub fn total_xp(player: &Object) -> i32 {
+  let spent: i32 = (1..player.level).map(level_up_xp).sum();
+  spent + player.fighter.map_or(0, |f| f.xp)
+}
+
+fn level_up(stat: Stat, objects: &mut [Object], game: &mut Game) {
+  let player = &mut objects[PLAYER];
+  let level_up_xp = level_up_xp(player.level);
+
+  player.level += 1;
+  game.log.add(format!("Your battle skills grow stronger! You reached level {}!", player.level), colors::YELLOW);
+  game.events.push(Event::LevelUp { level: player.level });
+
+  let fighter = player.fighter.as_mut().unwrap();
+  fighter.xp -= level_up_xp;
+  match stat {
+    Stat::Constitution => {
+      fighter.max_hp += 20;
+      fighter.hp += 20;
+    }
+    Stat::Strength => {
+      fighter.power += 1;
+    }
+    Stat::Agility => {
+      fighter.defense += 1;
+    }
+  }
+}
+
+
+// Puts the current level away in `game.levels` and brings out level `to`,
+// making it first if it's never been visited. Returns whether it was new.
+fn change_level(to: u32, objects: &mut Vec<Object>, game: &mut Game) -> bool {
+  game.stats.record_level_change(game.dungeon_level, to, game.turn);
+  let map = mem::take(&mut game.map);
+  let level_objects = objects.split_off(PLAYER + 1);
+  game.levels.insert(game.dungeon_level, Level { map, objects: level_objects });
+
+  game.dungeon_level = to;
+  match game.levels.remove(&to) {
+    Some(level) => {
+      game.map = level.map;
+      objects.extend(level.objects);
+      false
+    }
+    None => {
+      game.map = make_map(objects, to, &game.defs, &mut game.rng);
+      true
+    }
+  }
+}
+
+fn move_player_to_stairs(stairs: Stairs, objects: &mut [Object]) {
+  if let Some((x, y)) = objects.iter().find(|object| object.stairs == Some(stairs)).map(|object| object.pos()) {
+    objects[PLAYER].set_pos(x, y);
+  }
+}
+
+fn next_level(objects: &mut Vec<Object>, game: &mut Game) {
+  let level = game.dungeon_level + 1;
+  if game.levels.contains_key(&level) {
+    game.log.add("You descend the stairs again.", colors::RED);
+    change_level(level, objects, game);
+    move_player_to_stairs(Stairs::Up, objects);
+  } else {
+    game.log.add("You take a moment to rest, and recover your strength", colors::VIOLET);
+    let heal_hp = objects[PLAYER].max_hp(game) / 2;
+    objects[PLAYER].heal(heal_hp, game);
+
+    game.log.add("After a rare moment of peace, you descend deeper into \
+      the heart of the dungeon...", colors::RED);
+    change_level(level, objects, game);
+  }
+  game.events.push(Event::Descended { dungeon_level: game.dungeon_level });
+}
+
+fn previous_level(objects: &mut Vec<Object>, game: &mut Game) {
+  if game.dungeon_level <= 1 {
+    return;
+  }
+  game.log.add("You climb back up the stairs.", colors::RED);
+  let level = game.dungeon_level - 1;
+  change_level(level, objects, game);
+  move_player_to_stairs(Stairs::Down, objects);
+  game.events.push(Event::Ascended { dungeon_level: game.dungeon_level });
+}
+
+
+pub fn new_game(seed: u64, defs: Definitions) -> GameState {
+  let mut player = Object::new(0, 0, '%', "player", colors::WHITE, true);
+  player.alive = true;
+  player.fighter = Some( Fighter {
+    max_hp: 100,
+    hp: 100,
+    defense: 1,
+    power: 4,
+    xp: 0,
+    on_death: DeathCallback::Player,
+    on_hit: None,
+    speed: NORMAL_SPEED,
+    vision: TORCH_RADIUS,
+    flee_below: 0,
+    ranged: None,
+  });
+  player.energy = FULL_TURN;
+  let mut objects = vec![player];
+  let mut rng = GameRng::new(seed);
+  let mut game = Game {
+    map: make_map(&mut objects, 1, &defs, &mut rng),
+    log: Messages::default(),
+    inventory: vec![],
+    dungeon_level: 1,
+    levels: BTreeMap::new(),
+    turn: 0,
+    cause_of_death: None,
+    killed_by: None,
+    stats: RunStats { deepest_level: 1, ..Default::default() },
+    rng,
+    replay: Some(Replay::new(seed, &defs)),
+    events: vec![],
+    defs,
+  };
+
+  game.log.add("Welcome stranger! Prepare to perish in the Tombs of The Glass Oak.", colors::RED);
+
+  GameState::new(objects, game)
+}
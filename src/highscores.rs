@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use serde_json;
+
+use game::{total_xp, GameState};
+use save::data_dir;
+
+pub const HIGH_SCORES_FILE: &str = "highscores.json";
+pub const MAX_HIGH_SCORES: usize = 10;
+
+// One finished run on the high-score table.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HighScore {
+  // the slot the run was played in
+  pub name: String,
+  pub score: u64,
+  pub character_level: i32,
+  pub deepest_level: u32,
+  pub kills: u32,
+  pub turns: u64,
+  pub cause_of_death: Option<String>,
+  // seconds since the Unix epoch
+  pub died_at: u64,
+}
+
+impl HighScore {
+  pub fn new(state: &GameState, name: &str, died_at: u64) -> Self {
+    let game = &state.game;
+    HighScore {
+      name: name.into(),
+      score: game.stats.score(total_xp(state.player()), game.turn),
+      character_level: state.player().level,
+      deepest_level: game.stats.deepest_level.max(game.dungeon_level),
+      kills: game.stats.total_kills(),
+      turns: game.turn,
+      cause_of_death: game.cause_of_death.clone(),
+      died_at,
+    }
+  }
+}
+
+pub fn high_scores_path() -> PathBuf {
+  data_dir().join(HIGH_SCORES_FILE)
+}
+
+// The table, best first. No file yet just means no scores yet.
+pub fn load_high_scores() -> Result<Vec<HighScore>, Box<dyn Error>> {
+  let mut json = String::new();
+  match File::open(high_scores_path()) {
+    Ok(mut file) => file.read_to_string(&mut json)?,
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(e) => return Err(e.into()),
+  };
+  let scores = serde_json::from_str(&json)
+    .map_err(|e| -> Box<dyn Error> { From::from(format!("The high scores are damaged ({}).", e)) })?;
+  Ok(scores)
+}
+
+// Puts the run on the table if it's good enough, and returns where it placed,
+// counting from 0. Ties go below the runs that got there first.
+pub fn record_high_score(entry: HighScore) -> Result<Option<usize>, Box<dyn Error>> {
+  let mut scores = load_high_scores()?;
+  let place = scores.iter().position(|score| entry.score > score.score).unwrap_or(scores.len());
+  if place >= MAX_HIGH_SCORES {
+    return Ok(None);
+  }
+  scores.insert(place, entry);
+  scores.truncate(MAX_HIGH_SCORES);
+
+  let path = high_scores_path();
+  let temp_path = path.with_extension("json.tmp");
+  fs::create_dir_all(data_dir())?;
+  {
+    let mut file = File::create(&temp_path)?;
+    file.write_all(serde_json::to_string_pretty(&scores)?.as_bytes())?;
+    file.sync_all()?;
+  }
+  fs::rename(&temp_path, &path)?;
+  Ok(Some(place))
+}
@@ -0,0 +1,385 @@
+use colors;
+use fov::Fov;
+use game::{Event, Game, MessageLog, PLAYER};
+use object::{Killer, Object, Slot};
+use status::{apply_status, StatusKind};
+
+// how much weight the player can carry; see `Definitions::item_weight`
+pub const CARRY_CAPACITY: i32 = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Item {
+  Heal,
+  Lightning,
+  Confuse,
+  Fireball,
+  Haste,
+  Regeneration,
+  Slow,
+  Sword,
+  Shield,
+  Armor,
+  Helm,
+  Ring,
+}
+
+// What kind of thing an item is, for grouping them in the inventory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+  Potion,
+  Scroll,
+  Equipment,
+}
+
+impl Category {
+  pub fn name(self) -> &'static str {
+    match self {
+      Category::Potion => "Potions",
+      Category::Scroll => "Scrolls",
+      Category::Equipment => "Equipment",
+    }
+  }
+}
+
+// What a frontend has to ask the player for before `Action::UseItem` can go through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Targeting {
+  None,
+  Tile { max_range: Option<f32> },
+  Monster { max_range: Option<f32> },
+}
+
+impl Item {
+  // `range` is the item's, from its definition.
+  pub fn targeting(self, range: i32) -> Targeting {
+    use self::Item::*;
+    match self {
+      Heal | Lightning | Haste | Regeneration | Sword | Shield | Armor | Helm | Ring => Targeting::None,
+      Confuse | Slow => Targeting::Monster { max_range: Some(range as f32) },
+      Fireball => Targeting::Tile { max_range: None },
+    }
+  }
+
+  pub fn category(self) -> Category {
+    use self::Item::*;
+    match self {
+      Heal | Haste | Regeneration => Category::Potion,
+      Lightning | Confuse | Fireball | Slow => Category::Scroll,
+      Sword | Shield | Armor | Helm | Ring => Category::Equipment,
+    }
+  }
+}
+
+// What using an item does: the item's place in the inventory, the target the
+// player picked if it needed one, and the world to do it to.
+type UseFn = fn(usize, Option<(i32, i32)>, &mut [Object], &mut Game, &Fov) -> UseResult;
+
+pub enum UseResult {
+  UsedUp,
+  UsedAndKept,
+  Cancelled,
+}
+
+
+fn closest_monster(max_range: i32, objects: &[Object], fov: &Fov) -> Option<usize> {
+  let mut closest_enemy = None;
+  let mut closest_dist = (max_range + 1) as f32; // start slightly above max
+
+  for (id, object) in objects.iter().enumerate() {
+    if (id != PLAYER) && object.fighter.is_some() && object.ai.is_some() && fov.is_in_fov(object.x, object.y) {
+      let dist = objects[PLAYER].distance_to(object);
+      if dist < closest_dist {
+        closest_enemy = Some(id);
+        closest_dist = dist;
+      }
+    }
+  }
+  closest_enemy
+}
+
+// The tile a targeted item was aimed at, if it is one the player could have picked.
+fn valid_target(target: Option<(i32, i32)>, max_range: Option<f32>, objects: &[Object], fov: &Fov) -> Option<(i32, i32)> {
+  target.and_then(|(x, y)| {
+    let in_range = max_range.is_none_or(|range| objects[PLAYER].distance(x, y) <= range);
+    if fov.is_in_fov(x, y) && in_range {
+      Some((x, y))
+    } else {
+      None
+    }
+  })
+}
+
+
+// How strong the item being used is and how far it reaches, from its
+// definition.
+fn power_and_range(inventory_id: usize, game: &Game) -> (i32, i32) {
+  let name = &game.inventory[inventory_id].name;
+  (game.defs.item_power(name), game.defs.item_range(name))
+}
+
+
+fn cast_fireball(inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, fov: &Fov) -> UseResult {
+  let (damage, radius) = power_and_range(inventory_id, game);
+  let (x, y) = match valid_target(target, None, objects, fov) {
+    Some(tile_pos) => tile_pos,
+    None => return UseResult::Cancelled
+  };
+  game.log.add(format!("The fireball explodes, burning everything within {} tiles!", radius), colors::ORANGE);
+
+  let mut xp_to_gain = 0;
+  for (id, obj) in objects.iter_mut().enumerate() {
+    if obj.distance(x, y) <= radius as f32 && obj.fighter.is_some() {
+      game.log.add(format!("The {} gets burned for {} hit points", obj.name, damage), colors::ORANGE);
+      if id != PLAYER {
+        game.stats.damage_dealt += damage;
+      }
+      // the player isn't killed by themselves, but by their fireball
+      let killer = if id == PLAYER { Killer::Environment("a fireball") } else { Killer::Player };
+      if let Some(xp) = obj.take_damage(damage, killer, game) {
+        if id != PLAYER {
+          xp_to_gain += xp;
+        }
+      }
+    }
+  }
+  objects[PLAYER].fighter.as_mut().unwrap().xp += xp_to_gain;
+
+  UseResult::UsedUp
+}
+
+
+fn cast_lightning(inventory_id: usize, _target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, fov: &Fov) -> UseResult {
+  let (damage, range) = power_and_range(inventory_id, game);
+  let monster_id = closest_monster(range, objects, fov);
+  if let Some(monster_id) = monster_id {
+    game.log.add(format!("A lightning bolt strikes the {} with a loud thunder! The damage is {} hit points.", objects[monster_id].name, damage), colors::LIGHT_BLUE);
+    game.stats.damage_dealt += damage;
+    if let Some(xp) = objects[monster_id].take_damage(damage, Killer::Player, game) {
+      objects[PLAYER].fighter.as_mut().unwrap().xp += xp;
+    }
+    UseResult::UsedUp
+  } else { // no enemy found
+    game.log.add("No enemy is close enough to strike.", colors::RED);
+    UseResult::Cancelled
+  }
+}
+
+
+// Puts `kind` on the monster the player aimed at, for as many turns as the
+// item's power.
+fn cast_status(kind: StatusKind, inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, fov: &Fov) -> UseResult {
+  let (turns, range) = power_and_range(inventory_id, game);
+  let monster_id = valid_target(target, Some(range as f32), objects, fov).and_then(|(x, y)| {
+    objects.iter().enumerate().position(|(id, obj)| {
+      obj.pos() == (x, y) && obj.fighter.is_some() && id != PLAYER
+    })
+  });
+  if let Some(monster_id) = monster_id {
+    apply_status(&mut objects[monster_id], kind, turns, game);
+    UseResult::UsedUp
+  } else {
+    game.log.add("No enemy is close enough to strike.", colors::RED);
+    UseResult::Cancelled
+  }
+}
+
+fn cast_confuse(inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, fov: &Fov) -> UseResult {
+  cast_status(StatusKind::Confused, inventory_id, target, objects, game, fov)
+}
+
+fn cast_slow(inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, fov: &Fov) -> UseResult {
+  cast_status(StatusKind::Slowed, inventory_id, target, objects, game, fov)
+}
+
+
+fn drink_haste(inventory_id: usize, _target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, _fov: &Fov) -> UseResult {
+  let (turns, _) = power_and_range(inventory_id, game);
+  apply_status(&mut objects[PLAYER], StatusKind::Hasted, turns, game);
+  UseResult::UsedUp
+}
+
+fn drink_regeneration(inventory_id: usize, _target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, _fov: &Fov) -> UseResult {
+  let (turns, _) = power_and_range(inventory_id, game);
+  apply_status(&mut objects[PLAYER], StatusKind::Regenerating, turns, game);
+  UseResult::UsedUp
+}
+
+
+fn cast_heal(inventory_id: usize, _target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, _fov: &Fov) -> UseResult {
+  if let Some(fighter) = objects[PLAYER].fighter {
+    if fighter.hp == objects[PLAYER].max_hp(game) {
+      game.log.add("You are already at full health.", colors::RED);
+      return UseResult::Cancelled;
+    }
+    game.log.add("Your wounds start to feel better!", colors::LIGHT_VIOLET);
+    let (amount, _) = power_and_range(inventory_id, game);
+    objects[PLAYER].heal(amount, game);
+    return UseResult::UsedUp;
+  }
+  UseResult::Cancelled
+}
+
+
+fn get_equipped_in_slot(slot: Slot, inventory: &[Object]) -> Option<usize> {
+  inventory.iter().position(|item| {
+    item.equipment.is_some_and(|e| e.equipped && e.slot == slot)
+  })
+}
+
+fn equip(inventory_id: usize, game: &mut Game) {
+  let item = &mut game.inventory[inventory_id];
+  if let Some(ref mut equipment) = item.equipment {
+    if !equipment.equipped {
+      equipment.equipped = true;
+      game.log.add(format!("Equipped {} as your {}.", item.name, equipment.slot), colors::LIGHT_GREEN);
+      game.events.push(Event::Equipped { name: item.name.clone(), slot: equipment.slot });
+    }
+  }
+}
+
+fn dequip(inventory_id: usize, game: &mut Game) {
+  let item = &mut game.inventory[inventory_id];
+  if let Some(ref mut equipment) = item.equipment {
+    if equipment.equipped {
+      equipment.equipped = false;
+      game.log.add(format!("Took off {} from your {}.", item.name, equipment.slot), colors::LIGHT_YELLOW);
+      game.events.push(Event::Unequipped { name: item.name.clone(), slot: equipment.slot });
+    }
+  }
+}
+
+// Taking off something that added to max hp can leave the player with more hp
+// than they can now have.
+fn clamp_hp(player: &mut Object, game: &Game) {
+  let max_hp = player.max_hp(game);
+  if let Some(ref mut fighter) = player.fighter {
+    fighter.hp = fighter.hp.min(max_hp);
+  }
+}
+
+// Using a piece of equipment puts it on, swapping out whatever was in its
+// slot, or takes it off if it was already on.
+fn toggle_equipment(inventory_id: usize, _target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, _fov: &Fov) -> UseResult {
+  let equipment = match game.inventory[inventory_id].equipment {
+    Some(equipment) => equipment,
+    None => return UseResult::Cancelled,
+  };
+  if equipment.equipped {
+    dequip(inventory_id, game);
+  } else {
+    if let Some(current) = get_equipped_in_slot(equipment.slot, &game.inventory) {
+      dequip(current, game);
+    }
+    equip(inventory_id, game);
+  }
+  clamp_hp(&mut objects[PLAYER], game);
+  UseResult::UsedAndKept
+}
+
+
+// Whether two items can share a stack: the same kind of thing, and not
+// something to wear, since those are equipped one at a time.
+pub fn stacks_with(item: &Object, other: &Object) -> bool {
+  item.item.is_some() && item.item == other.item && item.name == other.name &&
+    item.equipment.is_none() && other.equipment.is_none()
+}
+
+// "healing potion", or "3 healing potions" for a stack of them.
+pub fn stack_name(item: &Object) -> String {
+  if item.count == 1 {
+    return item.name.clone();
+  }
+  // "scroll of fireball" goes to "scrolls of fireball"
+  let plural = match item.name.find(" of ") {
+    Some(of) => format!("{}s{}", &item.name[..of], &item.name[of..]),
+    None => format!("{}s", item.name),
+  };
+  format!("{} {}", item.count, plural)
+}
+
+// "a healing potion", or "3 healing potions".
+fn a_stack(item: &Object) -> String {
+  if item.count == 1 {
+    format!("a {}", item.name)
+  } else {
+    stack_name(item)
+  }
+}
+
+// How heavy the whole stack is.
+pub fn item_weight(item: &Object, game: &Game) -> i32 {
+  game.defs.item_weight(&item.name) * item.count as i32
+}
+
+pub fn carried_weight(game: &Game) -> i32 {
+  game.inventory.iter().map(|item| item_weight(item, game)).sum()
+}
+
+
+// Says whether the item got used, rather than cancelled or refused.
+pub fn use_item(inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object], game: &mut Game, fov: &Fov) -> bool {
+  use self::Item::*;
+
+  if let Some(item) = game.inventory[inventory_id].item {
+    let on_use: UseFn = match item {
+      Heal => cast_heal,
+      Lightning => cast_lightning,
+      Confuse => cast_confuse,
+      Fireball => cast_fireball,
+      Haste => drink_haste,
+      Regeneration => drink_regeneration,
+      Slow => cast_slow,
+      Sword | Shield | Armor | Helm | Ring => toggle_equipment,
+    };
+    match on_use(inventory_id, target, objects, game, fov) {
+      UseResult::UsedUp => {
+        let stack = &mut game.inventory[inventory_id];
+        let name = stack.name.clone();
+        if stack.count > 1 {
+          stack.count -= 1;
+        } else {
+          game.inventory.remove(inventory_id);
+        }
+        game.stats.record_item_used(&name);
+        game.events.push(Event::ItemUsed { item });
+        true
+      },
+      UseResult::UsedAndKept => true,
+      UseResult::Cancelled => {
+        game.log.add("Cancelled", colors::WHITE);
+        false
+      }
+    }
+  } else {
+    game.log.add(format!("The {} cannot be used.", game.inventory[inventory_id].name), colors::WHITE);
+    false
+  }
+}
+
+
+pub fn drop_item(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
+  dequip(inventory_id, game);
+  let mut item = game.inventory.remove(inventory_id);
+  clamp_hp(&mut objects[PLAYER], game);
+  item.set_pos(objects[PLAYER].x, objects[PLAYER].y);
+  game.log.add(format!("You dropped {}.", a_stack(&item)), colors::YELLOW);
+  game.events.push(Event::ItemDropped { name: item.name.clone() });
+  objects.push(item);
+}
+
+
+// Says whether the item was picked up; it can be too heavy to.
+pub fn pick_item_up(object_id: usize, objects: &mut Vec<Object>, game: &mut Game) -> bool {
+  if carried_weight(game) + item_weight(&objects[object_id], game) > CARRY_CAPACITY {
+    game.log.add(format!("You're carrying too much to pick up {}.", a_stack(&objects[object_id])), colors::RED);
+    return false;
+  }
+  let item = objects.swap_remove(object_id);
+  game.log.add(format!("You picked up {}!", a_stack(&item)), colors::GREEN);
+  game.events.push(Event::ItemPickedUp { name: item.name.clone() });
+  match game.inventory.iter_mut().find(|stack| stacks_with(stack, &item)) {
+    Some(stack) => stack.count += item.count,
+    None => game.inventory.push(item),
+  }
+  true
+}
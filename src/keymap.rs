@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use serde_json;
+
+pub const KEYMAP_FILE: &str = "keymap.json";
+pub const DEFAULT_PRESET: &str = "numpad";
+
+// The keymaps the game comes with, by name. Each is a complete set of
+// bindings; a keymap file can start from one and change what it likes.
+const PRESETS: &[(&str, &str)] = &[
+  ("numpad", include_str!("../keymaps/numpad.json")),
+  ("vi", include_str!("../keymaps/vi.json")),
+  ("wasd", include_str!("../keymaps/wasd.json")),
+];
+
+// Keys that aren't a single character, by the names a keymap file uses for
+// them.
+const NAMED_KEYS: &[&str] = &[
+  "Escape", "Backspace", "Tab", "Enter", "Spacebar", "Pause", "Insert", "Delete",
+  "Home", "End", "PageUp", "PageDown", "Up", "Down", "Left", "Right",
+  "NumPad0", "NumPad1", "NumPad2", "NumPad3", "NumPad4",
+  "NumPad5", "NumPad6", "NumPad7", "NumPad8", "NumPad9",
+  "NumPadAdd", "NumPadSubtract", "NumPadMultiply", "NumPadDivide", "NumPadDecimal", "NumPadEnter",
+  "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12",
+];
+
+// Everything a key can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+  MoveNorth,
+  MoveSouth,
+  MoveWest,
+  MoveEast,
+  MoveNorthWest,
+  MoveNorthEast,
+  MoveSouthWest,
+  MoveSouthEast,
+  Wait,
+  PickUp,
+  Drop,
+  Inventory,
+  Character,
+  Descend,
+  Ascend,
+  Look,
+  Explore,
+  Messages,
+  Help,
+  Exit,
+  Fullscreen,
+}
+
+impl Command {
+  // in the order the help screen lists them
+  pub const ALL: &'static [Command] = &[
+    Command::MoveNorth, Command::MoveSouth, Command::MoveWest, Command::MoveEast,
+    Command::MoveNorthWest, Command::MoveNorthEast, Command::MoveSouthWest, Command::MoveSouthEast,
+    Command::Wait, Command::PickUp, Command::Drop, Command::Inventory, Command::Character,
+    Command::Descend, Command::Ascend, Command::Look, Command::Explore, Command::Messages, Command::Help,
+    Command::Exit, Command::Fullscreen,
+  ];
+
+  pub fn description(self) -> &'static str {
+    use self::Command::*;
+    match self {
+      MoveNorth => "Move up",
+      MoveSouth => "Move down",
+      MoveWest => "Move left",
+      MoveEast => "Move right",
+      MoveNorthWest => "Move up and left",
+      MoveNorthEast => "Move up and right",
+      MoveSouthWest => "Move down and left",
+      MoveSouthEast => "Move down and right",
+      Wait => "Wait a turn",
+      PickUp => "Pick up an item",
+      Drop => "Drop an item",
+      Inventory => "Use an item",
+      Character => "Character information",
+      Descend => "Go down stairs, or walk to them",
+      Ascend => "Go up stairs, or walk to them",
+      Look => "Look around",
+      Explore => "Explore until something happens",
+      Messages => "Message history",
+      Help => "This help",
+      Exit => "Save and quit",
+      Fullscreen => "Toggle fullscreen",
+    }
+  }
+
+  // Which way a move command goes.
+  pub fn direction(self) -> Option<(i32, i32)> {
+    use self::Command::*;
+    match self {
+      MoveNorth => Some((0, -1)),
+      MoveSouth => Some((0, 1)),
+      MoveWest => Some((-1, 0)),
+      MoveEast => Some((1, 0)),
+      MoveNorthWest => Some((-1, -1)),
+      MoveNorthEast => Some((1, -1)),
+      MoveSouthWest => Some((-1, 1)),
+      MoveSouthEast => Some((1, 1)),
+      _ => None,
+    }
+  }
+}
+
+// A key with the modifiers held down with it, as written in a keymap file:
+// "k", ">", "Up", "NumPad8", "Ctrl+p", "Alt+Enter" or "Shift+Tab". Shift only
+// counts for named keys, since for the rest it's already in the character.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyPress {
+  pub key: String,
+  pub ctrl: bool,
+  pub alt: bool,
+  pub shift: bool,
+}
+
+impl KeyPress {
+  pub fn new(key: &str, ctrl: bool, alt: bool, shift: bool) -> Self {
+    let shift = shift && key.chars().count() > 1;
+    KeyPress { key: key.into(), ctrl, alt, shift }
+  }
+
+  pub fn parse(spec: &str) -> Result<KeyPress, String> {
+    let mut parts: Vec<&str> = spec.split('+').collect();
+    // "+" and "Ctrl++" are the plus key itself
+    if spec.ends_with("++") || spec == "+" {
+      parts.pop();
+      parts.pop();
+      parts.push("+");
+    }
+    let key = parts.pop().unwrap_or("");
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    for modifier in parts {
+      match modifier.to_lowercase().as_str() {
+        "ctrl" => ctrl = true,
+        "alt" => alt = true,
+        "shift" => shift = true,
+        _ => return Err(format!("\"{}\": {} isn't Ctrl, Alt or Shift", spec, modifier)),
+      }
+    }
+    let single_char = key.chars().count() == 1 && key.chars().all(|c| c.is_ascii_graphic());
+    if !single_char && !NAMED_KEYS.contains(&key) {
+      return Err(format!("\"{}\": there's no key called {}", spec, key));
+    }
+    Ok(KeyPress::new(key, ctrl, alt, shift))
+  }
+}
+
+impl fmt::Display for KeyPress {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.ctrl {
+      write!(f, "Ctrl+")?;
+    }
+    if self.alt {
+      write!(f, "Alt+")?;
+    }
+    if self.shift {
+      write!(f, "Shift+")?;
+    }
+    write!(f, "{}", self.key)
+  }
+}
+
+// A keymap file: the preset to start from, if any, and bindings from key to
+// command on top of it. Binding a key to null takes it out of the preset.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+  #[serde(default)]
+  preset: Option<String>,
+  #[serde(default)]
+  bindings: BTreeMap<String, Option<Command>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keymap {
+  bindings: Vec<(KeyPress, Command)>,
+}
+
+impl Keymap {
+  pub fn preset(name: &str) -> Result<Keymap, Box<dyn Error>> {
+    match PRESETS.iter().find(|preset| preset.0 == name) {
+      Some(preset) => Keymap::parse(preset.1),
+      None => {
+        let names: Vec<&str> = PRESETS.iter().map(|preset| preset.0).collect();
+        Err(From::from(format!("there's no preset called \"{}\" (there's {})", name, names.join(", "))))
+      }
+    }
+  }
+
+  pub fn is_preset(name: &str) -> bool {
+    PRESETS.iter().any(|preset| preset.0 == name)
+  }
+
+  pub fn load(path: &str) -> Result<Keymap, Box<dyn Error>> {
+    let mut json = String::new();
+    File::open(path)
+      .and_then(|mut file| file.read_to_string(&mut json))
+      .map_err(|e| format!("could not read {}: {}", path, e))?;
+    Keymap::parse(&json).map_err(|e| From::from(format!("{}: {}", path, e)))
+  }
+
+  pub fn parse(json: &str) -> Result<Keymap, Box<dyn Error>> {
+    let file: KeymapFile = serde_json::from_str(json)?;
+    let mut keymap = match file.preset {
+      Some(ref preset) => Keymap::preset(preset)?,
+      None => Keymap { bindings: vec![] },
+    };
+    for (spec, command) in file.bindings {
+      let key = KeyPress::parse(&spec)?;
+      keymap.bindings.retain(|binding| binding.0 != key);
+      if let Some(command) = command {
+        keymap.bindings.push((key, command));
+      }
+    }
+    if keymap.keys_for(Command::Exit).is_empty() {
+      return Err(From::from("nothing is bound to Exit, so there'd be no way out of the game"));
+    }
+    Ok(keymap)
+  }
+
+  pub fn command(&self, key: &KeyPress) -> Option<Command> {
+    self.bindings.iter().find(|binding| binding.0 == *key).map(|binding| binding.1)
+  }
+
+  pub fn keys_for(&self, command: Command) -> Vec<&KeyPress> {
+    self.bindings.iter().filter(|binding| binding.1 == command).map(|binding| &binding.0).collect()
+  }
+}
+
+impl Default for Keymap {
+  fn default() -> Self {
+    Keymap::preset(DEFAULT_PRESET).expect("the default keymap is invalid")
+  }
+}
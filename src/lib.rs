@@ -0,0 +1,31 @@
+extern crate rand;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate serde_json;
+
+pub mod ai;
+pub mod colors;
+pub mod definitions;
+pub mod explore;
+pub mod fov;
+pub mod game;
+pub mod highscores;
+pub mod item;
+pub mod keymap;
+pub mod map;
+pub mod messages;
+pub mod morgue;
+pub mod object;
+pub mod pathfinding;
+pub mod ranged;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod scheduler;
+pub mod stats;
+pub mod status;
+#[doc(hidden)]
+pub mod testing;
+
+pub use colors::Color;
+pub use game::{Action, Event, Game, GameState, Stat};
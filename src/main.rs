@@ -1,235 +1,325 @@
 extern crate tcod;
-extern crate rand;
-extern crate serde;
-#[macro_use] extern crate serde_derive;
-extern crate serde_json;
+extern crate the_glass_oak;
 
 use tcod::console::*;
 use tcod::colors;
 use tcod::Color;
-use tcod::map::{Map as FovMap, FovAlgorithm};
 use tcod::input::{self, Event, Mouse, Key};
-use rand::Rng;
 use std::cmp;
-use std::io::{Read, Write};
-use std::fs::File;
-use std::error::Error;
-use rand::distributions::{Weighted, WeightedChoice, IndependentSample};
+use std::env;
+use std::path::Path;
+use std::process;
+
+use the_glass_oak::{Action, GameState, Stat};
+use the_glass_oak::colors as game_colors;
+use the_glass_oak::definitions::{Definitions, DEFINITIONS_FILE};
+use the_glass_oak::explore::{explore_step, known_stairs, travel_step, Stopped};
+use the_glass_oak::game::{self, level_up_xp, new_game, Game, MessageLog, PLAYER};
+use the_glass_oak::highscores::{load_high_scores, record_high_score, HighScore};
+use the_glass_oak::item::{carried_weight, stack_name, Category, Item, Targeting, CARRY_CAPACITY};
+use the_glass_oak::keymap::{Command, KeyPress, Keymap, KEYMAP_FILE};
+use the_glass_oak::map::{MAP_WIDTH, MAP_HEIGHT};
+use the_glass_oak::messages::{Message, Messages, DEFAULT_MESSAGE_LIMIT};
+use the_glass_oak::morgue::write_morgue;
+use the_glass_oak::object::{Object, Stairs};
+use the_glass_oak::replay::{list_replays, replay_path, Replay, ReplayFile, GAME_VERSION};
+use the_glass_oak::rng::GameRng;
+use the_glass_oak::save::{data_dir, delete_slot, format_timestamp, import_old_save, list_slots, load_game, save_dir, save_game, slot_exists, unix_time, valid_slot_char, valid_slot_name, SaveError, Slot};
+use the_glass_oak::scheduler::effective_speed;
+use the_glass_oak::status::StatusKind;
 
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
 const LIMIT_FPS: i32 = 20;
-const MAP_WIDTH: i32 = 80;
-const MAP_HEIGHT: i32 = 43;
 const COLOR_LIGHT_WALL: Color =  Color { r: 130, g: 110, b: 50};
 const COLOR_DARK_WALL: Color = Color { r: 0, g: 0, b: 100 };
 const COLOR_LIGHT_GROUND: Color = Color { r: 200, g: 180, b: 50};
 const COLOR_DARK_GROUND: Color = Color { r: 50, g: 50, b: 150 };
-const ROOM_MAX_SIZE: i32 = 10;
-const ROOM_MIN_SIZE: i32 = 6;
-const MAX_ROOMS: i32 = 30;
-const FOV_ALGO: FovAlgorithm = FovAlgorithm::Basic;
-const FOV_LIGHT_WALLS: bool = true;
-const TORCH_RADIUS: i32 = 12;
-const LIGHTNING_RANGE: i32 = 5;
-const LIGHTNING_DAMAGE: i32 = 40;
-const FIREBALL_RADIUS: i32 = 3;
-const FIREBALL_DAMAGE: i32 = 25;
-const CONFUSE_NUM_TURNS: i32 = 10;
-const CONFUSE_RANGE: i32 = 8;
-const PLAYER: usize = 0;
-const HEAL_AMOUNT: i32 = 40;
-const LEVEL_UP_BASE: i32 = 200;
-const LEVEL_UP_FACTOR: i32 = 150;
 const MSG_X: i32 = BAR_WIDTH + 2;
 const MSG_WIDTH: i32 = SCREEN_WIDTH - BAR_WIDTH - 2;
 const MSG_HEIGHT: usize = PANEL_HEIGHT as usize - 1;
 const INVENTORY_WIDTH: i32 = 50;
+// items on each page of the inventory, leaving room for category headings
+const INVENTORY_PAGE_SIZE: usize = 20;
+// options on each page of a `paged_menu`
+const MENU_PAGE_SIZE: usize = 20;
+const ERROR_BOX_WIDTH: i32 = 50;
+const SLOT_MENU_WIDTH: i32 = 64;
+const HIGH_SCORES_WIDTH: i32 = 72;
+const MAX_SLOT_NAME_LENGTH: usize = 24;
+const MAX_SEARCH_LENGTH: usize = 40;
 const LEVEL_SCREEN_WIDTH: i32 = 40;
 const CHARACTER_SCREEN_WIDTH: i32 = 30;
+const LOOK_BOX_WIDTH: i32 = 30;
+const HELP_WIDTH: i32 = 56;
+// the message history uses the whole screen but its title and help lines
+const HISTORY_HEIGHT: i32 = SCREEN_HEIGHT - 2;
 
 const BAR_WIDTH: i32 = 20;
 const PANEL_HEIGHT: i32 = 7;
 const PANEL_Y: i32 = SCREEN_HEIGHT - PANEL_HEIGHT;
 
-trait MessageLog {
-  fn add<T: Into<String>>(&mut self, message: T, color: Color);
+struct Tcod {
+  root: Root,
+  con: Offscreen,
+  panel: Offscreen,
+  mouse: Mouse,
+  keymap: Keymap,
+  // whether auto-explore picks things up on its way
+  auto_pickup: bool,
 }
 
-impl MessageLog for Vec<(String, Color)> {
-  fn add<T: Into<String>>(&mut self, message: T, color: Color) {
-    self.push((message.into(), color));
-  }
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlayerAction {
+  TookTurn,
+  DidntTakeTurn,
+  Exit,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Game {
-  map: Map,
-  log: Messages,
-  inventory: Vec<Object>,
-  dungeon_level: u32,
+
+fn tcod_color(color: game_colors::Color) -> Color {
+  Color { r: color.r, g: color.g, b: color.b }
 }
 
-struct Tcod {
-  root: Root,
-  con: Offscreen,
-  panel: Offscreen,
-  fov: FovMap,
-  mouse: Mouse,
+
+trait Draw {
+  fn draw(&self, con: &mut dyn Console);
+  fn clear(&self, con: &mut dyn Console);
 }
 
-enum UseResult {
-  UsedUp,
-  Cancelled,
+impl Draw for Object {
+  fn draw (&self, con: &mut dyn Console) {
+    con.set_default_foreground(tcod_color(self.color));
+    con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
+  }
+
+  fn clear (&self, con: &mut dyn Console) {
+    con.put_char(self.x, self.y, ' ', BackgroundFlag::None);
+  }
 }
 
-struct Transition {
-  level: u32,
-  value: u32,
+
+// What the cursor is being used for, which decides where Enter or a left
+// click is allowed to settle on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CursorMode {
+  // just looking around; nothing gets chosen
+  Look,
+  Tile { max_range: Option<f32> },
+  Monster { max_range: Option<f32> },
 }
 
+fn monster_at(state: &GameState, (x, y): (i32, i32)) -> Option<usize> {
+  state.objects.iter().enumerate()
+    .position(|(id, obj)| obj.pos() == (x, y) && obj.fighter.is_some() && id != PLAYER)
+}
 
-fn from_dungeon_level(table: &[Transition], level: u32) -> u32 {
-  table.iter()
-    .rev()
-    .find(|transition| level >= transition.level)
-    .map_or(0, |transition| transition.value)
+// The monsters the player can see, nearest first.
+fn visible_monsters(state: &GameState) -> Vec<usize> {
+  let mut monsters: Vec<usize> = (0..state.objects.len()).filter(|&id| {
+    let obj = &state.objects[id];
+    id != PLAYER && obj.fighter.is_some() && state.fov.is_in_fov(obj.x, obj.y)
+  }).collect();
+  monsters.sort_by(|&a, &b| {
+    let player = state.player();
+    player.distance_to(&state.objects[a]).partial_cmp(&player.distance_to(&state.objects[b])).unwrap()
+  });
+  monsters
 }
 
+// Where a key moves the cursor: wherever it would move the player, and the
+// arrows and numpad always work too.
+fn cursor_direction(key: Key, keymap: &Keymap) -> Option<(i32, i32)> {
+  use tcod::input::KeyCode::*;
+  if let Some(direction) = keymap.command(&key_press(key)).and_then(Command::direction) {
+    return Some(direction);
+  }
+  match key.code {
+    Up | NumPad8 => Some((0, -1)),
+    Down | NumPad2 => Some((0, 1)),
+    Left | NumPad4 => Some((-1, 0)),
+    Right | NumPad6 => Some((1, 0)),
+    Home | NumPad7 => Some((-1, -1)),
+    PageUp | NumPad9 => Some((1, -1)),
+    End | NumPad1 => Some((-1, 1)),
+    PageDown | NumPad3 => Some((1, 1)),
+    _ => None,
+  }
+}
 
-fn target_monster(tcod: &mut Tcod, objects: &Vec<Object>, game: &mut Game, max_range: Option<f32>) -> Option<usize> {
-  loop {
-    match target_tile(tcod, objects, game, max_range) {
-      Some((x, y)) => {
-        for (id, obj) in objects.iter().enumerate() {
-          if obj.pos() == (x, y) && obj.fighter.is_some() && id != PLAYER {
-            return Some(id)
-          }
-        }
-      },
-      None => return None,
+// How hurt something looks, going by how much of its health is left.
+fn wound_description(hp: i32, max_hp: i32) -> &'static str {
+  match hp * 100 / max_hp.max(1) {
+    100 ..= i32::MAX => "unhurt",
+    75 ..= 99 => "lightly wounded",
+    50 ..= 74 => "wounded",
+    25 ..= 49 => "badly wounded",
+    _ => "almost dead",
+  }
+}
+
+// A few lines about what's at the tile, for the box shown next to the cursor.
+fn describe_tile(state: &GameState, (x, y): (i32, i32)) -> Vec<(String, Color)> {
+  if !state.fov.is_in_fov(x, y) {
+    let explored = state.game.map[x as usize][y as usize].explored;
+    let text = if explored { "You can't see there right now." } else { "You haven't been there." };
+    return vec![(text.into(), colors::LIGHT_GREY)];
+  }
+
+  let mut lines = vec![];
+  for obj in state.objects.iter().filter(|obj| obj.pos() == (x, y)) {
+    let name = if obj.name == "player" { "you".to_string() } else { obj.name.clone() };
+    lines.push((name, tcod_color(obj.color)));
+    if let Some(fighter) = obj.fighter {
+      lines.push((format!("  {}", wound_description(fighter.hp, obj.max_hp(&state.game))), colors::WHITE));
+    }
+    for status in &obj.statuses {
+      lines.push((format!("  {} ({})", status.kind.name(), status.turns), tcod_color(status.kind.color())));
     }
   }
+  if lines.is_empty() {
+    let wall = state.game.map[x as usize][y as usize].block_sight;
+    lines.push((if wall { "a wall" } else { "the floor" }.into(), colors::LIGHT_GREY));
+  }
+  lines
 }
 
-fn target_tile(tcod: &mut Tcod, objects: &Vec<Object>, game: &mut Game, max_range: Option<f32>) -> Option<(i32, i32)> {
-  use tcod::input::KeyCode::Escape;
+// Moves a cursor around the map, by keyboard or mouse, describing whatever
+// it's over. The movement keys move it, Tab (Shift-Tab backwards) jumps
+// between the monsters in view, and Enter or a left click picks the tile under
+// it if the mode allows. Escape or a right click gives up, as does Enter when
+// only looking.
+fn choose_tile(tcod: &mut Tcod, state: &GameState, mode: CursorMode) -> Option<(i32, i32)> {
+  use tcod::input::KeyCode::*;
+
+  let in_range = |(x, y): (i32, i32)| match mode {
+    CursorMode::Look => true,
+    CursorMode::Tile { max_range } | CursorMode::Monster { max_range } =>
+      max_range.map_or(true, |range| state.player().distance(x, y) <= range),
+  };
+  let valid = |(x, y): (i32, i32)| match mode {
+    CursorMode::Look => false,
+    CursorMode::Tile { .. } => state.fov.is_in_fov(x, y) && in_range((x, y)),
+    CursorMode::Monster { .. } => state.fov.is_in_fov(x, y) && in_range((x, y)) && monster_at(state, (x, y)).is_some(),
+  };
+
+  let first_monster = visible_monsters(state).into_iter().map(|id| state.objects[id].pos())
+    .find(|&pos| mode != CursorMode::Look && in_range(pos));
+  let mut cursor = first_monster.unwrap_or_else(|| state.player().pos());
+  let mut mouse_at = (tcod.mouse.cx, tcod.mouse.cy);
 
   loop {
+    render_all(tcod, state, false);
+    let cursor_color = if mode == CursorMode::Look || valid(cursor) { colors::LIGHT_YELLOW } else { colors::DARK_RED };
+    tcod.root.set_char_background(cursor.0, cursor.1, cursor_color, BackgroundFlag::Set);
+    render_description(&describe_tile(state, cursor), cursor, &mut tcod.root);
     tcod.root.flush();
-    let event = input::check_for_event(input::KEY_PRESS | input::MOUSE).map(|e| e.1);
+
     let mut key = None;
-    match event {
+    match input::check_for_event(input::KEY_PRESS | input::MOUSE).map(|e| e.1) {
       Some(Event::Mouse(m)) => tcod.mouse = m,
       Some(Event::Key(k)) => key = Some(k),
       None => {}
     }
-    render_all(tcod, objects, game, false);
 
-    let (x, y) = (tcod.mouse.cx as i32, tcod.mouse.cy as i32);
-    let in_fov = (x < MAP_WIDTH) && (y < MAP_HEIGHT) && tcod.fov.is_in_fov(x, y);
-    let in_range = max_range.map_or(true, |range| objects[PLAYER].distance(x, y) <= range);
-    if tcod.mouse.lbutton_pressed && in_fov && in_range {
-      return Some((x, y))
+    let mouse = (tcod.mouse.cx as i32, tcod.mouse.cy as i32);
+    let on_map = mouse.0 < MAP_WIDTH && mouse.1 < MAP_HEIGHT;
+    if (tcod.mouse.cx, tcod.mouse.cy) != mouse_at && on_map {
+      cursor = mouse;
     }
-    let escape = key.map_or(false, |k| k.code == Escape);
-    if tcod.mouse.rbutton_pressed || escape {
-      return None
+    mouse_at = (tcod.mouse.cx, tcod.mouse.cy);
+    if tcod.mouse.lbutton_pressed && on_map && valid(mouse) {
+      return Some(mouse);
     }
-  }
-}
-
-
-fn closest_monster(max_range: i32, objects: &mut Vec<Object>, tcod: &Tcod) -> Option<usize> {
-  let mut closest_enemy = None;
-  let mut closest_dist = (max_range + 1) as f32; // start slightly above max
-
-  for (id, object) in objects.iter().enumerate() {
-    if (id != PLAYER) && object.fighter.is_some() && object.ai.is_some() && tcod.fov.is_in_fov(object.x, object.y) {
-      let dist = objects[PLAYER].distance_to(object);
-      if dist < closest_dist {
-        closest_enemy = Some(id);
-        closest_dist = dist;
-      }
+    if tcod.mouse.rbutton_pressed {
+      return None;
     }
-  }
-  closest_enemy
-}
-
 
-fn cast_fireball(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) -> UseResult {
-  game.log.add("Left-click a target tile for the fireball, or right-click to cancel.", colors::LIGHT_CYAN);
-  let (x, y) = match target_tile(tcod, objects, game, None) {
-    Some(tile_pos) => tile_pos,
-    None => return UseResult::Cancelled
-  };
-  game.log.add(format!("The fireball explodes, burning everything within {} tiles!", FIREBALL_RADIUS), colors::ORANGE);
-
-  let mut xp_to_gain = 0;
-  for (id, obj) in objects.iter_mut().enumerate() {
-    if obj.distance(x, y) <= FIREBALL_RADIUS as f32 && obj.fighter.is_some() {
-      game.log.add(format!("The {} gets burned for {} hit points", obj.name, FIREBALL_DAMAGE), colors::ORANGE);
-      if let Some(xp) = obj.take_damage(FIREBALL_DAMAGE, game) {
-        if id != PLAYER {
-          xp_to_gain += xp;
+    let key = match key {
+      Some(key) => key,
+      None => continue,
+    };
+    if let Some((dx, dy)) = cursor_direction(key, &tcod.keymap) {
+      cursor = ((cursor.0 + dx).clamp(0, MAP_WIDTH - 1), (cursor.1 + dy).clamp(0, MAP_HEIGHT - 1));
+      continue;
+    }
+    match key.code {
+      Tab => {
+        let monsters: Vec<(i32, i32)> = visible_monsters(state).into_iter().map(|id| state.objects[id].pos()).collect();
+        if !monsters.is_empty() {
+          let next = match monsters.iter().position(|&pos| pos == cursor) {
+            Some(current) if key.shift => (current + monsters.len() - 1) % monsters.len(),
+            Some(current) => (current + 1) % monsters.len(),
+            None => 0,
+          };
+          cursor = monsters[next];
         }
       }
+      Enter | NumPadEnter if mode == CursorMode::Look => return None,
+      Enter | NumPadEnter if valid(cursor) => return Some(cursor),
+      Escape => return None,
+      _ => {}
     }
   }
-  objects[PLAYER].fighter.as_mut().unwrap().xp += xp_to_gain;
+}
 
-  UseResult::UsedUp
+// The box describing what's under the cursor, kept to whichever side of the
+// map the cursor isn't on.
+fn render_description(lines: &[(String, Color)], cursor: (i32, i32), root: &mut Root) {
+  let height = lines.len() as i32;
+  let mut window = Offscreen::new(LOOK_BOX_WIDTH, height);
+  for (y, &(ref text, color)) in lines.iter().enumerate() {
+    window.set_default_foreground(color);
+    window.print_ex(0, y as i32, BackgroundFlag::None, TextAlignment::Left, text.as_str());
+  }
+  let x = if cursor.0 < MAP_WIDTH / 2 { MAP_WIDTH - LOOK_BOX_WIDTH - 1 } else { 1 };
+  blit(&window, (0, 0), (LOOK_BOX_WIDTH, height), root, (x, 1), 1.0, 0.7);
 }
 
+fn target_tile(tcod: &mut Tcod, state: &GameState, max_range: Option<f32>) -> Option<(i32, i32)> {
+  choose_tile(tcod, state, CursorMode::Tile { max_range })
+}
 
-fn cast_lightning(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) -> UseResult {
-  let monster_id = closest_monster(LIGHTNING_RANGE, objects, tcod);
-  if let Some(monster_id) = monster_id {
-    game.log.add(format!("A lightning bolt strikes the {} with a loud thunder! The damage is {} hit points.", objects[monster_id].name, LIGHTNING_DAMAGE), colors::LIGHT_BLUE);
-    if let Some(xp) = objects[monster_id].take_damage(LIGHTNING_DAMAGE, game) {
-      objects[PLAYER].fighter.as_mut().unwrap().xp += xp;
-    }
-    UseResult::UsedUp
-  } else { // no enemy found
-    game.log.add("No enemy is close enough to strike.", colors::RED);
-    UseResult::Cancelled
-  }
+fn target_monster(tcod: &mut Tcod, state: &GameState, max_range: Option<f32>) -> Option<usize> {
+  choose_tile(tcod, state, CursorMode::Monster { max_range }).and_then(|pos| monster_at(state, pos))
 }
 
 
-fn cast_confuse(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) -> UseResult {
-  game.log.add("Left-click an enemy to confuse it, right-click to cancel.", colors::LIGHT_CYAN);
-  let monster_id = target_monster(tcod, objects, game, Some(CONFUSE_RANGE as f32));
-  if let Some(monster_id) = monster_id {
-    let old_ai = objects[monster_id].ai.take().unwrap_or(Ai::Basic);
-    objects[monster_id].ai = Some(Ai::Confused {
-      previous_ai: Box::new(old_ai),
-      num_turns: CONFUSE_NUM_TURNS,
-    });
-    game.log.add(format!("The eyes of {} look vacant, as he starts to stumble around!", objects[monster_id].name), colors::LIGHT_GREEN);
-    UseResult::UsedUp
-  } else {
-    game.log.add("No enemy is close enough to strike.", colors::RED);
-    UseResult::Cancelled
+fn targeting_prompt(item: Item) -> &'static str {
+  match item {
+    Item::Fireball => "Choose a target tile for the fireball with the movement keys or mouse, Enter or left-click to throw it, Escape to cancel.",
+    Item::Confuse => "Choose an enemy to confuse (Tab cycles through them), Enter or left-click to cast, Escape to cancel.",
+    Item::Slow => "Choose an enemy to slow (Tab cycles through them), Enter or left-click to cast, Escape to cancel.",
+    _ => "Choose a target (Tab cycles through enemies), Enter or left-click to confirm, Escape to cancel.",
   }
 }
 
-
-fn cast_heal(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) -> UseResult {
-  if let Some(fighter) = objects[PLAYER].fighter {
-    if fighter.hp == fighter.max_hp {
-      game.log.add("You are already at full health.", colors::RED);
-      return UseResult::Cancelled;
+fn use_item(tcod: &mut Tcod, inventory_id: usize, state: &mut GameState) -> PlayerAction {
+  let stack = &state.game.inventory[inventory_id];
+  let range = state.game.defs.item_range(&stack.name);
+  let target = match stack.item.map(|item| (item, item.targeting(range))) {
+    Some((item, Targeting::Tile { max_range })) => {
+      state.game.log.add(targeting_prompt(item), game_colors::LIGHT_CYAN);
+      target_tile(tcod, state, max_range)
     }
-    game.log.add("Your wounds start to feel better!", colors::LIGHT_VIOLET);
-    objects[PLAYER].heal(HEAL_AMOUNT);
-    return UseResult::UsedUp;
-  }
-  UseResult::Cancelled
+    Some((item, Targeting::Monster { max_range })) => {
+      state.game.log.add(targeting_prompt(item), game_colors::LIGHT_CYAN);
+      target_monster(tcod, state, max_range).map(|id| state.objects[id].pos())
+    }
+    _ => None,
+  };
+  player_step(tcod, state, Action::UseItem { inventory_id, target })
 }
 
+
 fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root) -> Option<usize> {
   assert!(options.len() <= 26, "Cannot have a menu with more than 26 options.");
+  let key = show_menu(header, options, width, root);
+  menu_choice(key, options.len())
+}
+
+// Draws a menu and waits for a key.
+fn show_menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root) -> Key {
   let header_height = if header.is_empty() {
     0
   } else {
@@ -254,10 +344,14 @@ fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root)
   tcod::console::blit(&mut window, (0, 0), (width, height), root, (x, y), 1.0, 0.7);
 
   root.flush();
-  let key = root.wait_for_keypress(true);
-  if key.printable.is_alphabetic() {
+  root.wait_for_keypress(true)
+}
+
+// The option the key's letter picks, out of `options` of them, if any.
+fn menu_choice(key: Key, options: usize) -> Option<usize> {
+  if key.printable.is_ascii_alphabetic() {
     let index = key.printable.to_ascii_lowercase() as usize - 'a' as usize;
-    if index < options.len() {
+    if index < options {
       Some(index)
     } else {
       None
@@ -267,612 +361,129 @@ fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root)
   }
 }
 
-fn inventory_menu(inventory: &Vec<Object>, header: &str, root: &mut Root) -> Option<usize> {
-  let options = if inventory.len() == 0 {
-    vec!["Inventory is empty.".into()]
-  } else {
-    inventory.iter().map(|item| { item.name.clone() }).collect()
-  };
-
-  let inventory_index = menu(header, &options, INVENTORY_WIDTH, root);
-
-  if inventory.len() > 0 {
-    inventory_index
-  } else {
-    None
-  }
-}
-
-fn use_item(tcod: &mut Tcod, inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
-  use Item::*;
-
-  if let Some(item) = game.inventory[inventory_id].item {
-    let on_use: fn(usize, &mut Vec<Object>, &mut Game, &mut Tcod) -> UseResult = match item {
-      Heal => cast_heal,
-      Lightning => cast_lightning,
-      Confuse => cast_confuse,
-      Fireball => cast_fireball,
-    };
-    match on_use(inventory_id, objects, game, tcod) {
-      UseResult::UsedUp => {
-        game.inventory.remove(inventory_id);
-      },
-      UseResult::Cancelled => {
-        game.log.add("Cancelled", colors::WHITE);
-      }
-    }
-  } else {
-    game.log.add(format!("The {} cannot be used.", game.inventory[inventory_id].name), colors::WHITE);
-  }
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-enum Item {
-  Heal,
-  Lightning,
-  Confuse,
-  Fireball,
-}
-
-
-fn drop_item(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
-  let mut item = game.inventory.remove(inventory_id);
-  item.set_pos(objects[PLAYER].x, objects[PLAYER].y);
-  game.log.add(format!("You dropped a {}.", item.name), colors::YELLOW);
-  objects.push(item);
-}
-
-
-fn pick_item_up(object_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
-  if game.inventory.len() >= 26 {
-    game.log.add(format!("Your inventory is full. Cannot pick up {}.",
-    objects[object_id].name), colors::RED);
-  } else {
-    let item = objects.swap_remove(object_id);
-    game.log.add(format!("You picked up a {}!", item.name), colors::GREEN);
-    game.inventory.push(item);
-  }
-}
-
-
-fn get_names_under_mouse(mouse: Mouse, objects: &Vec<Object>, fov_map: &FovMap) -> String {
-  let (x, y) = (mouse.cx as i32, mouse.cy as i32);
-
-  let names = objects
-    .iter()
-    .filter(|obj| {obj.pos() == (x, y) && fov_map.is_in_fov(obj.x, obj.y)})
-    .map(|obj| obj.name.clone())
-    .collect::<Vec<_>>();
-
-  names.join(", ")
-}
-
-type Messages = Vec<(String, Color)>;
-
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-struct Fighter {
-  max_hp: i32,
-  hp: i32,
-  defense: i32,
-  power: i32,
-  xp: i32,
-  on_death: DeathCallback,
-}
+// A menu with any number of options, shown a page at a time and turned with
+// '<' and '>' (or Page Up and Page Down) like the inventory. Returns the
+// index into all of `options`.
+fn paged_menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root) -> Option<usize> {
+  use tcod::input::KeyCode::{PageDown, PageUp};
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-enum DeathCallback {
-  Player,
-  Monster,
-}
-
-impl DeathCallback {
-  fn callback(self, object: &mut Object, game: &mut Game) {
-    use DeathCallback::*;
-    let callback: fn(&mut Object, &mut Game) = match self {
-      Player => player_death,
-      Monster => monster_death,
+  let pages: Vec<&[T]> = options.chunks(MENU_PAGE_SIZE).collect();
+  let mut page = 0;
+  loop {
+    let page_header = if pages.len() > 1 {
+      format!("{}Page {} of {}, '<' and '>' to turn\n", header, page + 1, pages.len())
+    } else {
+      header.to_string()
     };
-    object.always_visible = true;
-    callback(object, game);
-  }
-}
-
-fn player_death(player: &mut Object, game: &mut Game) {
-  game.log.add("You died!", colors::DARK_RED);
-  player.char = '@';
-  player.color = colors::DARK_RED;
-}
-
-fn monster_death(monster: &mut Object, game: &mut Game) {
-  game.log.add(format!("{} is dead! You gain {} xp.", monster.name, monster.fighter.unwrap().xp), colors::DARK_RED);
-  monster.char = '@';
-  monster.color = colors::DARK_RED;
-  monster.blocks = false;
-  monster.fighter = None;
-  monster.ai = None;
-  monster.name = format!("remains of {}", monster.name);
-}
-
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-enum Ai{
-  Basic,
-  Confused{previous_ai: Box<Ai>, num_turns: i32},
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum PlayerAction {
-  TookTurn,
-  DidntTakeTurn,
-  Exit,
-}
-
-type Map = Vec<Vec<Tile>>;
-
-fn make_map(objects: &mut Vec<Object>, level: u32) -> Map {
-  // fills map with unblocked tiles... odd macro syntax!
-  let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
-  assert_eq!(&objects[PLAYER] as *const _, &objects[0] as *const _);
-  objects.truncate(1);
-
-  let mut rooms = vec![];
-
-  for _ in 0..MAX_ROOMS {
-    let w = rand::thread_rng().gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-    let h = rand::thread_rng().gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-
-    let x = rand::thread_rng().gen_range(0, MAP_WIDTH - w);
-    let y = rand::thread_rng().gen_range(0, MAP_HEIGHT - h);
-
-    let new_room = Rect::new(x, y, w, h);
-    let failed = rooms.iter().any(|other_room| new_room.intersects_with(other_room));
-
-    if !failed {
-      create_room(new_room, &mut map);
-      place_objects(new_room, &map, objects, level);
-
-      let (new_x, new_y) = new_room.center();
-
-      if rooms.is_empty() {
-        objects[PLAYER].set_pos(new_x, new_y);
-      } else {
-        let (prev_x, prev_y) = rooms[rooms.len() - 1].center();
-
-        if rand::random() {
-          create_h_tunnel(prev_x, new_x, prev_y, &mut map);
-          create_v_tunnel(prev_y, new_y, new_x, &mut map);
-        } else {
-          create_v_tunnel(prev_y, new_y, prev_x, &mut map);
-          create_h_tunnel(prev_x, new_x, new_y, &mut map);
-        }
-      }
-      rooms.push(new_room);
-    }
-  }
-
-  let (last_room_x, last_room_y) = rooms[rooms.len() - 1].center();
-  let mut stairs = Object::new(last_room_x, last_room_y, '<', "stairs", colors::WHITE, false);
-  stairs.always_visible = true;
-  objects.push(stairs);
-
-  map
-}
-
-fn create_room(room: Rect, map: &mut Map) {
-  for x in (room.x1 + 1) .. room.x2 {
-    for y in (room.y1 + 1) .. room.y2 {
-      map[x as usize][y as usize] = Tile::empty();
+    let key = show_menu(&page_header, pages[page], width, root);
+    match key {
+      Key { printable: '>', .. } | Key { code: PageDown, .. } if page + 1 < pages.len() => page += 1,
+      Key { printable: '<', .. } | Key { code: PageUp, .. } if page > 0 => page -= 1,
+      _ => return menu_choice(key, pages[page].len()).map(|index| page * MENU_PAGE_SIZE + index),
     }
   }
 }
 
-fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
-  for x in cmp::min(x1, x2)..(cmp::max(x1, x2)+1) {
-    map[x as usize][y as usize] = Tile::empty();
-  }
-}
-
-fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
-  for y in cmp::min(y1, y2)..(cmp::max(y1, y2)+1) {
-    map[x as usize][y as usize] = Tile::empty();
-  }
-}
-
-
-#[derive(Clone, Copy, Debug)]
-struct Rect {
-  x1: i32,
-  y1: i32,
-  x2: i32,
-  y2: i32,
-}
+// The inventory, grouped by category and shown a page at a time. The letters
+// pick an item on the page shown, and '<' and '>' (or Page Up and Page Down)
+// turn the page. Any other key cancels.
+fn inventory_menu(game: &Game, header: &str, root: &mut Root) -> Option<usize> {
+  use tcod::input::KeyCode::{PageDown, PageUp};
 
-impl Rect {
-  pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
-    Rect { x1: x, y1: y, x2: x + w, y2: y + h }
+  if game.inventory.is_empty() {
+    menu(header, &["Inventory is empty."], INVENTORY_WIDTH, root);
+    return None;
   }
+  let mut order: Vec<usize> = (0..game.inventory.len()).collect();
+  order.sort_by_key(|&id| game.inventory[id].item.map(Item::category));
+  let pages: Vec<&[usize]> = order.chunks(INVENTORY_PAGE_SIZE).collect();
+  let mut page = 0;
 
-  pub fn center(&self) -> (i32, i32) {
-    let center_x = (self.x1+self.x2)/2;
-    let center_y = (self.y1+self.y2)/2;
-    (center_x, center_y)
-  }
-
-  pub fn intersects_with(&self, other: &Rect) -> bool {
-    (self.x1 <= other.x2) && (self.x2 >= other.x1) &&
-      (self.y1 <= other.y2) && (self.y2 >= other.y1)
-  }
-}
-
-
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-struct Tile {
-  blocked: bool,
-  block_sight: bool,
-  explored: bool,
-}
-
-impl Tile {
-  pub fn empty () -> Self {
-    Tile { blocked: false, explored: false, block_sight: false }
-  }
-
-  pub fn wall () -> Self {
-    Tile { blocked: true, explored: false, block_sight: true }
-  }
-}
-
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Object {
-  x: i32,
-  y: i32,
-  char: char,
-  color: Color,
-  name: String,
-  blocks: bool,
-  alive: bool,
-  always_visible: bool,
-  level: i32,
-  fighter: Option<Fighter>,
-  ai: Option<Ai>,
-  item: Option<Item>,
-}
-
-impl Object {
-  pub fn new (x: i32, y: i32, char: char, name: &str, color: Color, blocks: bool) -> Self {
-    Object {
-      x: x,
-      y: y,
-      char: char,
-      color: color,
-      name: name.into(),
-      blocks: blocks,
-      alive: false,
-      always_visible: false,
-      level: 1,
-      fighter: None,
-      ai: None,
-      item: None,
-    }
-  }
-
-  pub fn draw (&self, con: &mut Console) {
-    con.set_default_foreground(self.color);
-    con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
-  }
-
-  pub fn clear (&self, con: &mut Console) {
-    con.put_char(self.x, self.y, ' ', BackgroundFlag::None);
-  }
-
-  pub fn pos(&self) -> (i32, i32) {
-    (self.x, self.y)
-  }
-
-  pub fn set_pos(&mut self, x: i32, y: i32) {
-    self.x = x;
-    self.y = y;
-  }
-
-  pub fn distance_to(&self, other: &Object) -> f32 {
-    let dx = other.x - self.x;
-    let dy = other.y - self.y;
-    ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
-  }
-
-  pub fn distance(&self, x: i32, y: i32) -> f32 {
-    (((x - self.x).pow(2) + (y - self.y).pow(2)) as f32).sqrt()
-  }
-
-  pub fn take_damage(&mut self, damage: i32, game: &mut Game) -> Option<i32> {
-    if let Some(fighter) = self.fighter.as_mut() {
-      if damage > 0 {
-        fighter.hp -= damage;
-      }
-    }
-    if let Some(fighter) = self.fighter {
-      if fighter.hp <= 0 {
-        self.alive = false;
-        fighter.on_death.callback(self, game);
-        return Some(fighter.xp);
-      }
-    }
-    None
-  }
-
-  pub fn heal(&mut self, amount: i32) {
-    if let Some(ref mut fighter) = self.fighter {
-      fighter.hp += amount;
-      if fighter.hp > fighter.max_hp {
-        fighter.hp = fighter.max_hp;
-      }
+  loop {
+    let mut lines = vec![(format!("Carrying {}/{}", carried_weight(game), CARRY_CAPACITY), colors::LIGHT_GREY)];
+    if pages.len() > 1 {
+      lines.push((format!("Page {} of {}, '<' and '>' to turn", page + 1, pages.len()), colors::LIGHT_GREY));
     }
-  }
-
-  pub fn attack(&mut self, target: &mut Object, game: &mut Game) {
-    let damage = self.fighter.map_or(0, |f| f.power) - target.fighter.map_or(0, |f| f.defense);
-    if damage > 0 {
-      game.log.add(format!("{} attacks {} for {} hit points.", self.name, target.name, damage), colors::YELLOW);
-      if let Some(xp) = target.take_damage(damage, game) {
-        self.fighter.as_mut().unwrap().xp += xp;
+    let mut category = None;
+    for (index, &id) in pages[page].iter().enumerate() {
+      let item = &game.inventory[id];
+      let item_category = item.item.map(Item::category);
+      if item_category != category {
+        category = item_category;
+        lines.push((category.map_or("Other", Category::name).to_string(), colors::LIGHT_YELLOW));
       }
-    } else {
-      game.log.add(format!("{} attacks {} but it has no effect!", self.name, target.name),
-      colors::YELLOW);
+      let menu_letter = (b'a' + index as u8) as char;
+      let label = match item.equipment {
+        Some(equipment) if equipment.equipped => format!("{} (on {})", stack_name(item), equipment.slot),
+        _ => stack_name(item),
+      };
+      lines.push((format!("({}) {}", menu_letter, label), colors::WHITE));
     }
-  }
-}
-
-fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
-  assert!(first_index != second_index);
-  let split_at_index = cmp::max(first_index, second_index);
-  let (first_slice, second_slice) = items.split_at_mut(split_at_index);
-  if first_index < second_index {
-    (&mut first_slice[first_index], &mut second_slice[0])
-  } else {
-    (&mut second_slice[0], &mut first_slice[second_index])
-  }
-}
-
-fn move_by (id: usize, dx: i32, dy: i32, map: &Map, objects: &mut Vec<Object>) {
-  let (x, y) = objects[id].pos();
-  if !is_blocked(x+dx, y+dy, map, objects) {
-    objects[id].set_pos(x + dx, y + dy);
-  }
-}
-
-fn player_move_or_attack(dx: i32, dy: i32, objects: &mut Vec<Object>, game: &mut Game) {
-  let x = objects[PLAYER].x + dx;
-  let y = objects[PLAYER].y + dy;
-
-  let target_id = objects.iter().position(|object| {
-    object.fighter.is_some() && object.pos() == (x, y)
-  });
 
-  match target_id {
-    Some(target_id) => {
-      let (player, target) = mut_two(PLAYER, target_id, objects);
-      player.attack(target, game);
-    },
-    None => {
-      move_by(PLAYER, dx, dy, &game.map, objects);
+    let header_height = root.get_height_rect(0, 0, INVENTORY_WIDTH, SCREEN_HEIGHT, header);
+    let height = header_height + lines.len() as i32;
+    let mut window = Offscreen::new(INVENTORY_WIDTH, height);
+    window.set_default_foreground(colors::WHITE);
+    window.print_rect_ex(0, 0, INVENTORY_WIDTH, height, BackgroundFlag::None, TextAlignment::Left, header);
+    for (y, &(ref text, color)) in lines.iter().enumerate() {
+      window.set_default_foreground(color);
+      window.print_ex(0, header_height + y as i32, BackgroundFlag::None, TextAlignment::Left, text.as_str());
     }
-  }
-}
-
-
-fn level_up(objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) {
-  let player = &mut objects[PLAYER];
-  let level_up_xp = LEVEL_UP_BASE + player.level * LEVEL_UP_FACTOR;
-
-  if player.fighter.as_ref().map_or(0, |f| f.xp) >= level_up_xp {
-    player.level += 1;
-    game.log.add(format!("Your battle skills grow stronger! You reached level {}!", player.level), colors::YELLOW);
-
-    let fighter = player.fighter.as_mut().unwrap();
-    let mut choice = None;
-    while choice.is_none() {
-      choice = menu(
-       "Level up! Choose a stat to raise:\n",
-        &[format!("Constitution: (+20 HP, from {}", fighter.max_hp),
-          format!("Strength (+1 attack, from {}", fighter.power),
-          format!("Agility (+1 defense, from {}", fighter.defense)],
-          LEVEL_SCREEN_WIDTH, &mut tcod.root);
-    };
-    fighter.xp -= level_up_xp;
-    match choice.unwrap() {
-      0 => {
-        fighter.max_hp += 20;
-        fighter.hp += 20;
-      }
-      1 => {
-        fighter.power += 1;
-      }
-      2 => {
-        fighter.defense += 1;
+    let x = SCREEN_WIDTH / 2 - INVENTORY_WIDTH / 2;
+    let y = SCREEN_HEIGHT / 2 - height / 2;
+    blit(&window, (0, 0), (INVENTORY_WIDTH, height), root, (x, y), 1.0, 0.7);
+    root.flush();
+
+    let key = root.wait_for_keypress(true);
+    match key {
+      Key { printable: '>', .. } | Key { code: PageDown, .. } if page + 1 < pages.len() => page += 1,
+      Key { printable: '<', .. } | Key { code: PageUp, .. } if page > 0 => page -= 1,
+      _ if key.printable.is_ascii_lowercase() => {
+        let index = key.printable as usize - 'a' as usize;
+        return pages[page].get(index).cloned();
       }
-      _ => unreachable!(),
+      _ => return None,
     }
   }
 }
 
 
-fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, level: u32) {
-  let max_monsters = from_dungeon_level(&[
-    Transition {level: 1, value: 2},
-    Transition {level: 4, value: 3},
-    Transition {level: 6, value: 5},
-  ], level);
-  let num_creatures = rand::thread_rng().gen_range(0, max_monsters + 1);
-
-  let troll_chance = from_dungeon_level(&[
-    Transition {level: 3, value: 15},
-    Transition {level: 5, value: 30},
-    Transition {level: 7, value: 60},
-  ], level);
-
-  for _ in 0..num_creatures {
-    let x = rand::thread_rng().gen_range(room.x1+1, room.x2);
-    let y = rand::thread_rng().gen_range(room.y1+1, room.y2);
-
-    if !is_blocked(x, y, map, objects) {
-      let monster_chances = &mut [
-        Weighted {weight: 80, item: "orc"},
-        Weighted {weight: troll_chance, item: "troll"},
-        Weighted {weight: 5, item: "npc"},
-      ];
-      let monster_choice = WeightedChoice::new(monster_chances);
-      let mut creature = match monster_choice.ind_sample(&mut rand::thread_rng()) {
-        "orc" => {
-          let mut orc = Object::new(x, y, 'o', &(String::from("orc-") + &(x+y).to_string()), colors::DESATURATED_GREEN, true);
-          orc.fighter = Some( Fighter {
-            max_hp: 20,
-            hp: 20,
-            defense: 0,
-            power: 4,
-            xp: 35,
-            on_death: DeathCallback::Monster,
-          });
-          orc.ai = Some(Ai::Basic);
-          orc
-        }
-        "troll" => {
-          let mut troll = Object::new(x, y, 'T', &(String::from("troll-") + &(x+y).to_string()), colors::DARKER_GREEN, true);
-          troll.fighter = Some( Fighter {
-            max_hp: 30,
-            hp: 30,
-            defense: 2,
-            power: 8,
-            xp: 100,
-            on_death: DeathCallback::Monster,
-          });
-          troll.ai = Some(Ai::Basic);
-          troll
-        }
-        "npc" => {
-          let mut npc = Object::new(x, y, '&', &(String::from("npc-") + &(x+y).to_string()), colors::YELLOW, true);
-          npc.fighter = Some( Fighter {
-            max_hp: 10,
-            hp: 10,
-            defense: 0,
-            power: 3,
-            xp: 10,
-            on_death: DeathCallback::Monster,
-          });
-          npc.ai = Some(Ai::Basic);
-          npc
-        }
-        _ => unreachable!(),
-      };
-
-      creature.alive = true;
-      objects.push(creature);
-    }
-  }
-
-  let max_items = from_dungeon_level(&[
-    Transition {level: 1, value: 1},
-    Transition {level: 4, value: 2},
-  ], level);
-
-  let num_items = rand::thread_rng().gen_range(0, max_items + 1);
-
-  for _ in 0..num_items {
-    let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
-    let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
-
-    if !is_blocked(x, y, map, objects) {
-      let item_chances = &mut [
-        Weighted {weight: 35, item: Item::Heal},
-        Weighted {weight: from_dungeon_level(&[Transition{level: 4,value: 25}], level), item: Item::Lightning},
-        Weighted {weight: from_dungeon_level(&[Transition{level: 6,value: 25}], level), item: Item::Fireball},
-        Weighted {weight: from_dungeon_level(&[Transition{level: 2,value: 10}], level), item: Item::Confuse},
-      ];
-      let item_choice = WeightedChoice::new(item_chances);
-      let mut item = match item_choice.ind_sample(&mut rand::thread_rng()) {
-        Item::Heal => {
-          let mut object = Object::new(x, y, '!', "healing potion", colors::VIOLET, false);
-          object.item = Some(Item::Heal);
-          object
-        }
-        Item::Lightning => {
-          let mut object = Object::new(x, y, '#', "scroll of lightning bolt", colors::LIGHT_YELLOW, false);
-          object.item = Some(Item::Lightning);
-          object
-        }
-        Item::Fireball => {
-          let mut object = Object::new(x, y, '#', "scroll of fireball", colors::LIGHT_YELLOW, false);
-          object.item = Some(Item::Fireball);
-          object
-        }
-        Item::Confuse => {
-          let mut object = Object::new(x, y, '#', "scroll of confusion", colors::LIGHT_YELLOW, false);
-          object.item = Some(Item::Confuse);
-          object
-        }
-      };
-
-      item.always_visible = true;
-      objects.push(item);
-    }
-  }
-}
-
-fn move_towards(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut Vec<Object>) {
-  let dx = target_x - objects[id].x;
-  let dy = target_y - objects[id].y;
-  let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
-
-  let dx = (dx as f32 / distance).round() as i32;
-  let dy = (dy as f32 / distance).round() as i32;
-  move_by(id, dx, dy, map, objects);
-}
+fn get_names_under_mouse(mouse: Mouse, state: &GameState) -> String {
+  let (x, y) = (mouse.cx as i32, mouse.cy as i32);
 
-fn ai_take_turn(monster_id: usize, objects: &mut Vec<Object>, fov_map: &FovMap, game: &mut Game) {
-  use Ai::*;
-  if let Some(ai) = objects[monster_id].ai.take() {
-    let new_ai = match ai {
-      Basic => ai_basic(monster_id, objects, fov_map, game),
-      Confused{previous_ai, num_turns} => ai_confused(monster_id, objects, game, previous_ai, num_turns)
-    };
-    objects[monster_id].ai = Some(new_ai);
-  }
-}
+  let names = state.objects
+    .iter()
+    .filter(|obj| {obj.pos() == (x, y) && state.fov.is_in_fov(obj.x, obj.y)})
+    .map(|obj| obj.name.clone())
+    .collect::<Vec<_>>();
 
-fn ai_basic(monster_id: usize, objects: &mut Vec<Object>, fov_map: &FovMap, game: &mut Game) -> Ai {
-  let (monster_x, monster_y) = objects[monster_id].pos();
-  if fov_map.is_in_fov(monster_x, monster_y) {
-    if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
-      let (player_x, player_y) = objects[PLAYER].pos();
-      move_towards(monster_id, player_x, player_y, &game.map, objects);
-    } else if objects[PLAYER].fighter.map_or(false, |f| f.hp > 0) {
-      let (monster, player) = mut_two(monster_id, PLAYER, objects);
-      monster.attack(player, game);
-    }
-  }
-  Ai::Basic
+  names.join(", ")
 }
 
 
-fn ai_confused(monster_id: usize, objects: &mut Vec<Object>, game: &mut Game, previous_ai: Box<Ai>, num_turns: i32) -> Ai {
-  if num_turns >= 0 {
-    move_by(monster_id, rand::thread_rng().gen_range(-1, 2), rand::thread_rng().gen_range(-1, 2), &game.map, objects);
-    Ai::Confused{previous_ai: previous_ai, num_turns: num_turns - 1}
-  } else {
-    game.log.add(format!("The {} is no longer confused!", objects[monster_id].name), colors::RED);
-    *previous_ai
-  }
+fn level_up(tcod: &mut Tcod, state: &mut GameState) {
+  let fighter = match state.player().fighter {
+    Some(fighter) => fighter,
+    None => return,
+  };
+  let mut choice = None;
+  while choice.is_none() {
+    choice = menu(
+     "Level up! Choose a stat to raise:\n",
+      &[format!("Constitution: (+20 HP, from {}", fighter.max_hp),
+        format!("Strength (+1 attack, from {}", fighter.power),
+        format!("Agility (+1 defense, from {}", fighter.defense)],
+        LEVEL_SCREEN_WIDTH, &mut tcod.root);
+  };
+  let stat = match choice.unwrap() {
+    0 => Stat::Constitution,
+    1 => Stat::Strength,
+    2 => Stat::Agility,
+    _ => unreachable!(),
+  };
+  state.step(Action::LevelUp(stat));
 }
 
-fn is_blocked(x: i32, y: i32, map: &Map, objects: &Vec<Object>) -> bool {
-  if map[x as usize][y as usize].blocked {
-    return true;
-  }
-
-  objects.iter().any(|object| {
-    object.blocks && object.pos() == (x, y)
-  })
-}
 
 fn render_bar(panel: &mut Offscreen, x: i32, y: i32, total_width: i32, name: &str, value: i32, maximum: i32, bar_color: Color, back_color: Color) {
   let bar_width = (value as f32 / maximum as f32 * total_width as f32) as i32;
@@ -891,21 +502,18 @@ fn render_bar(panel: &mut Offscreen, x: i32, y: i32, total_width: i32, name: &st
 }
 
 
-fn render_all(tcod: &mut Tcod, objects: &Vec<Object>, game: &mut Game, fov_recompute: bool) {
+fn render_all(tcod: &mut Tcod, state: &GameState, fov_recompute: bool) {
   let root = &mut tcod.root;
   let con = &mut tcod.con;
   let panel = &mut tcod.panel;
-  let fov_map = &mut tcod.fov;
   let mouse = tcod.mouse;
+  let game = &state.game;
   let mut y = MSG_HEIGHT as i32;
 
   if fov_recompute {
-    let player = &objects[PLAYER];
-    fov_map.compute_fov(player.x, player.y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
-
     for y in 0 .. MAP_HEIGHT {
       for x in 0 .. MAP_WIDTH {
-        let visible = fov_map.is_in_fov(x, y);
+        let visible = state.fov.is_in_fov(x, y);
         let wall = game.map[x as usize][y as usize].block_sight;
         let color = match (visible, wall) {
           // outside the fov
@@ -915,19 +523,15 @@ fn render_all(tcod: &mut Tcod, objects: &Vec<Object>, game: &mut Game, fov_recom
           (true, true) => COLOR_LIGHT_WALL,
           (true, false) => COLOR_LIGHT_GROUND,
         };
-        let explored = &mut game.map[x as usize][y as usize].explored;
-        if visible {
-          *explored = true;
-        }
-        if *explored {
+        if game.map[x as usize][y as usize].explored {
           con.set_char_background(x, y, color, BackgroundFlag::Set);
         }
       }
     }
   }
 
-  let mut to_draw: Vec<_> = objects.iter().filter(|o| {
-    fov_map.is_in_fov(o.x, o.y) || (o.always_visible && game.map[o.x as usize][o.y as usize].explored)
+  let mut to_draw: Vec<_> = state.objects.iter().filter(|o| {
+    state.fov.is_in_fov(o.x, o.y) || (o.always_visible && game.map[o.x as usize][o.y as usize].explored)
   }).collect();
   to_draw.sort_by(|o1, o2| { o1.blocks.cmp(&o2.blocks) });
   for object in &to_draw {
@@ -939,108 +543,176 @@ fn render_all(tcod: &mut Tcod, objects: &Vec<Object>, game: &mut Game, fov_recom
   panel.set_default_background(colors::BLACK);
   panel.clear();
 
-  let hp = objects[PLAYER].fighter.map_or(0, |f| f.hp);
-  let max_hp = objects[PLAYER].fighter.map_or(0, |f| f.max_hp);
+  let hp = state.player().fighter.map_or(0, |f| f.hp);
+  let max_hp = state.player().max_hp(game);
 
   render_bar(panel, 1, 1, BAR_WIDTH, "HP", hp, max_hp, colors::LIGHT_RED, colors::DARKER_RED);
 
   panel.print_ex(1, 3, BackgroundFlag::None, TextAlignment::Left, format!("Dungeon level: {}", game.dungeon_level));
 
+  for (line, status) in state.player().statuses.iter().enumerate().take(PANEL_HEIGHT as usize - 4) {
+    panel.set_default_foreground(tcod_color(status.kind.color()));
+    panel.print_ex(1, 4 + line as i32, BackgroundFlag::None, TextAlignment::Left, format!("{} ({})", status.kind.name(), status.turns));
+  }
+
   panel.set_default_foreground(colors::LIGHT_GREY);
-  panel.print_ex(1, 0, BackgroundFlag::None, TextAlignment::Left, get_names_under_mouse(mouse, &objects, fov_map));
+  panel.print_ex(1, 0, BackgroundFlag::None, TextAlignment::Left, get_names_under_mouse(mouse, state));
 
-  for &(ref msg, color) in game.log.iter().rev() {
-    let msg_height = panel.get_height_rect(MSG_X, y, MSG_WIDTH, 0, msg);
+  for message in game.log.iter().rev() {
+    let msg = message.display();
+    let msg_height = panel.get_height_rect(MSG_X, y, MSG_WIDTH, 0, &msg);
     y -= msg_height;
     if y < 0 {
       break;
     }
-    panel.set_default_foreground(color);
-    panel.print_rect(MSG_X, y, MSG_WIDTH, 0, msg);
+    panel.set_default_foreground(tcod_color(message.color));
+    panel.print_rect(MSG_X, y, MSG_WIDTH, 0, &msg);
   }
 
   blit(panel, (0, 0), (SCREEN_WIDTH, PANEL_HEIGHT), root, (0, PANEL_Y), 1.0, 1.0);
 }
 
-fn move_check ((pos_x, pos_y): (i32, i32), (move_x, move_y): (i32, i32), map: &Map, objects: &Vec<Object>) -> (i32, i32) {
-  if !is_blocked(pos_x+move_x, pos_y+move_y, map, objects) {
-    (move_x, move_y)
+
+fn player_step(tcod: &mut Tcod, state: &mut GameState, action: Action) -> PlayerAction {
+  let events = state.step(action);
+  animate_projectiles(tcod, state, &events);
+  if events.contains(&game::Event::TookTurn) {
+    PlayerAction::TookTurn
   } else {
-    (0, 0)
+    PlayerAction::DidntTakeTurn
   }
 }
 
-fn handle_keys(key: Key, tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) -> PlayerAction {
-  use PlayerAction::*;
-  use tcod::input::Key;
-  use tcod::input::KeyCode::*;
-
-  let player_alive = objects[PLAYER].alive;
-
-  match (key, player_alive) {
-    (Key { code: Up, .. }, true) | (Key { code: NumPad8, ..}, true) => {
-      player_move_or_attack(0, -1, objects, game);
-      TookTurn
-    }
-    (Key { code: Down, .. }, true) | (Key { code: NumPad2, ..}, true) => {
-      player_move_or_attack(0, 1, objects, game);
-      TookTurn
-    }
-    (Key { code: Left, .. }, true) | (Key { code: NumPad4, ..}, true) => {
-      player_move_or_attack(-1, 0, objects, game);
-      TookTurn
+// Shows every shot in `events` flying along its path a tile a frame, as far as
+// the player can see it.
+fn animate_projectiles(tcod: &mut Tcod, state: &GameState, events: &[game::Event]) {
+  for event in events {
+    if let game::Event::ProjectileFired { ref path, projectile, .. } = *event {
+      for &(x, y) in path.iter().filter(|&&(x, y)| state.fov.is_in_fov(x, y)) {
+        render_all(tcod, state, false);
+        tcod.root.put_char_ex(x, y, projectile.glyph(), tcod_color(projectile.color()), colors::BLACK);
+        tcod.root.flush();
+      }
     }
-    (Key { code: Right, .. }, true) | (Key { code: NumPad6, ..}, true) => {
-      player_move_or_attack(1, 0, objects, game);
-      TookTurn
+  }
+  for object in state.objects.iter() {
+    object.clear(&mut tcod.con);
+  }
+}
+
+// Walks the player a step at a time with `step`, drawing each one, until it
+// says to stop or a key is pressed.
+fn walk<F>(tcod: &mut Tcod, state: &mut GameState, mut step: F) -> PlayerAction
+  where F: FnMut(&mut GameState) -> Result<Vec<game::Event>, Stopped>
+{
+  let mut action = PlayerAction::DidntTakeTurn;
+  while !tcod.root.window_closed() {
+    match step(state) {
+      Ok(events) => {
+        animate_projectiles(tcod, state, &events);
+        if events.contains(&game::Event::TookTurn) {
+          action = PlayerAction::TookTurn;
+        }
+      }
+      Err(stopped) => {
+        animate_projectiles(tcod, state, &stopped.events);
+        if stopped.events.contains(&game::Event::TookTurn) {
+          action = PlayerAction::TookTurn;
+        }
+        break;
+      }
     }
-    (Key { code: Home, .. }, true) | (Key { code: NumPad7, ..}, true) => {
-      player_move_or_attack(-1, -1, objects, game);
-      TookTurn
+    render_all(tcod, state, true);
+    tcod.root.flush();
+    for object in state.objects.iter() {
+      object.clear(&mut tcod.con);
     }
-    (Key { code: PageUp, .. }, true) | (Key { code: NumPad9, ..}, true) => {
-      player_move_or_attack(1, -1, objects, game);
-      TookTurn
+    if let Some((_, Event::Key(_))) = input::check_for_event(input::KEY_PRESS) {
+      break;
     }
-    (Key { code: End, .. }, true) | (Key { code: NumPad1, ..}, true) => {
-      player_move_or_attack(-1, 1, objects, game);
-      TookTurn
+  }
+  action
+}
+
+// Walks to a tile the player clicked on, if they've seen it.
+fn travel_to(tcod: &mut Tcod, state: &mut GameState, to: (i32, i32)) -> PlayerAction {
+  if !state.game.map[to.0 as usize][to.1 as usize].explored {
+    return PlayerAction::DidntTakeTurn;
+  }
+  walk(tcod, state, |state| travel_step(state, to))
+}
+
+// Takes the stairs the player is standing on, or walks to the ones they've seen.
+fn use_stairs(tcod: &mut Tcod, state: &mut GameState, kind: Stairs) -> PlayerAction {
+  if state.stairs_under_player() == Some(kind) {
+    let events = state.step(if kind == Stairs::Down { Action::Descend } else { Action::Ascend });
+    if changed_level(&events) {
+      tcod.con.clear();
     }
-    (Key { code: PageDown, .. }, true) | (Key { code: NumPad3, ..}, true) => {
-      player_move_or_attack(1, 1, objects, game);
-      TookTurn
+    return PlayerAction::DidntTakeTurn;
+  }
+  match known_stairs(state, kind) {
+    Some(stairs) => walk(tcod, state, |state| travel_step(state, stairs)),
+    None => {
+      let which = if kind == Stairs::Down { "down" } else { "up" };
+      state.game.log.add(format!("You haven't found the stairs {} yet.", which), game_colors::LIGHT_GREY);
+      PlayerAction::DidntTakeTurn
     }
-    (Key { code: NumPad5, .. }, true) => {
-      TookTurn  // do nothing, i.e. wait for the monster to come to you
+  }
+}
+
+fn changed_level(events: &[game::Event]) -> bool {
+  events.iter().any(|event| matches!(*event, game::Event::Descended { .. } | game::Event::Ascended { .. }))
+}
+
+// A tcod key as the keymap knows it: by the character it types, or by name for
+// the keys that don't type one (and the numpad, which does).
+fn key_press(key: Key) -> KeyPress {
+  let numpad = format!("{:?}", key.code).starts_with("NumPad");
+  if key.printable.is_ascii_graphic() && !numpad {
+    KeyPress::new(&key.printable.to_string(), key.ctrl, key.alt, key.shift)
+  } else {
+    KeyPress::new(&format!("{:?}", key.code), key.ctrl, key.alt, key.shift)
+  }
+}
+
+fn handle_keys(key: Key, tcod: &mut Tcod, state: &mut GameState) -> PlayerAction {
+  use PlayerAction::*;
+
+  let player_alive = state.player().alive;
+  let command = match tcod.keymap.command(&key_press(key)) {
+    Some(command) => command,
+    None => return DidntTakeTurn,
+  };
+
+  match (command, player_alive) {
+    (Command::Wait, true) => {
+      player_step(tcod, state, Action::Wait)  // do nothing, i.e. wait for the monster to come to you
     }
-    (Key { printable: 'g', .. }, true) => {
-      let item_id = objects.iter().position(|object| {
-        object.pos() == objects[PLAYER].pos() && object.item.is_some()
-      });
-      if let Some(item_id) = item_id {
-        pick_item_up(item_id, objects, game);
-      }
-      DidntTakeTurn
+    (Command::PickUp, true) => {
+      player_step(tcod, state, Action::PickUp)
     },
-    (Key { printable: 'd', .. }, true) => {
-      let inventory_index = inventory_menu(&game.inventory, "Press the key next to an item  to drop it, or any other to cancel.\n", &mut tcod.root);
+    (Command::Drop, true) => {
+      let inventory_index = inventory_menu(&state.game, "Press the key next to an item  to drop it, or any other to cancel.\n", &mut tcod.root);
       if let Some(inventory_index) = inventory_index {
-        drop_item(inventory_index, objects, game);
+        player_step(tcod, state, Action::Drop(inventory_index))
+      } else {
+        DidntTakeTurn
       }
-      DidntTakeTurn
     },
-    (Key { printable: 'i', .. }, true) => {
-      let inventory_index = inventory_menu(&game.inventory, "Press the key next to an item to use it, or any other to cancel.\n", &mut tcod.root);
+    (Command::Inventory, true) => {
+      let inventory_index = inventory_menu(&state.game, "Press the key next to an item to use it, or any other to cancel.\n", &mut tcod.root);
 
       if let Some(inventory_index) = inventory_index {
-        use_item(tcod, inventory_index, objects, game);
+        use_item(tcod, inventory_index, state)
+      } else {
+        DidntTakeTurn
       }
-      DidntTakeTurn
     },
-    (Key { printable: 'c', .. }, true) => {
-      let player = &objects[PLAYER];
+    (Command::Character, true) => {
+      let player = state.player();
       let level = player.level;
-      let level_up_xp = LEVEL_UP_BASE + player.level * LEVEL_UP_FACTOR;
+      let level_up_xp = level_up_xp(player.level);
       if let Some(fighter) = player.fighter.as_ref() {
         let msg = format!("Character information
 
@@ -1048,57 +720,244 @@ Level: {}
 Experience: {}
 Experience to level up: {}
 
-Maximum HP: {}
-Attack: {}
-Defense: {}", level, fighter.xp, level_up_xp, fighter.max_hp, fighter.power, fighter.defense);
+Maximum HP: {} ({} + {} from equipment)
+Attack: {} ({} + {} from equipment)
+Defense: {} ({} + {} from equipment)
+Speed: {}
+
+Seed: {}", level, fighter.xp, level_up_xp,
+          player.max_hp(&state.game), fighter.max_hp, player.max_hp(&state.game) - fighter.max_hp,
+          player.power(&state.game), fighter.power, player.power(&state.game) - fighter.power,
+          player.defense(&state.game), fighter.defense, player.defense(&state.game) - fighter.defense,
+          effective_speed(player),
+          state.game.rng.seed());
         msgbox(&msg, CHARACTER_SCREEN_WIDTH, &mut tcod.root);
       }
       DidntTakeTurn
     },
-    (Key { printable: '<', .. }, true) => {
-      let player_on_stairs = objects.iter().any({|object|
-        object.pos() == objects[PLAYER].pos() && object.name == "stairs"
-      });
-      if player_on_stairs {
-        next_level(tcod, objects, game);
-      }
+    (Command::Descend, true) => use_stairs(tcod, state, Stairs::Down),
+    (Command::Ascend, true) => use_stairs(tcod, state, Stairs::Up),
+    (Command::Look, _) => {
+      choose_tile(tcod, state, CursorMode::Look);
+      DidntTakeTurn
+    },
+    (Command::Explore, true) => {
+      let pick_up = tcod.auto_pickup;
+      walk(tcod, state, |state| explore_step(state, pick_up))
+    },
+    (Command::Messages, _) => {
+      message_history(&state.game.log, &mut tcod.root);
       DidntTakeTurn
     },
-    (Key { code: Escape, .. }, _) => Exit,
-    (Key { code: Enter, alt: true, .. }, _) => {
+    (Command::Help, _) => {
+      help_screen(&tcod.keymap, &mut tcod.root);
+      DidntTakeTurn
+    },
+    (Command::Exit, _) => Exit,
+    (Command::Fullscreen, _) => {
       let fullscreen = tcod.root.is_fullscreen();
       tcod.root.set_fullscreen(!fullscreen);
       DidntTakeTurn
     },
+    (command, true) => match command.direction() {
+      Some((dx, dy)) => player_step(tcod, state, Action::Move(dx, dy)),
+      None => DidntTakeTurn,
+    },
     _ => DidntTakeTurn,
   }
 }
 
-
-fn save_game(objects: &Vec<Object>, game: &Game) -> Result<(), Box<Error>> {
-  let save_data = serde_json::to_string(&(objects, game))?;
-  let mut file = File::create("savegame")?;
-  file.write_all(save_data.as_bytes())?;
-  Ok(())
+// Every command and the keys bound to it in the keymap in use.
+fn help_screen(keymap: &Keymap, root: &mut Root) {
+  let mut text = String::from("Keys\n\n");
+  for &command in Command::ALL {
+    let keys: Vec<String> = keymap.keys_for(command).iter().map(|key| key.to_string()).collect();
+    let keys = if keys.is_empty() { "(not bound)".to_string() } else { keys.join(", ") };
+    text.push_str(&format!("{:<24}{}\n", command.description(), keys));
+  }
+  msgbox(&text, HELP_WIDTH, root);
 }
 
 
-fn load_game() -> Result<(Vec<Object>, Game), Box<Error>> {
-  let mut json_save_state = String::new();
-  let mut file = File::open("savegame")?;
-  file.read_to_string(&mut json_save_state)?;
-  let result = serde_json::from_str::<(Vec<Object>, Game)>(&json_save_state)?;
-  Ok(result)
+// Every message still in the log, newest at the bottom, scrolled with the
+// arrow keys, Page Up/Down and Home/End. '/' searches, showing only the
+// messages with that text in them, and Escape closes it.
+fn message_history(log: &Messages, root: &mut Root) {
+  use tcod::input::KeyCode::*;
+
+  let mut search: Option<String> = None;
+  // how many of the shown messages are hidden below the bottom of the screen
+  let mut scroll = 0;
+  loop {
+    let shown: Vec<&Message> = match search {
+      Some(ref search) => {
+        let search = search.to_lowercase();
+        log.iter().filter(|message| message.display().to_lowercase().contains(&search)).collect()
+      }
+      None => log.iter().collect(),
+    };
+    scroll = cmp::min(scroll, shown.len().saturating_sub(1));
+
+    let mut window = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    window.set_default_foreground(colors::LIGHT_YELLOW);
+    let title = match search {
+      Some(ref search) => format!("Messages with \"{}\" ({} of {})", search, shown.len(), log.len()),
+      None => format!("Message history ({} of the last {} kept)", log.len(), log.limit()),
+    };
+    window.print_ex(0, 0, BackgroundFlag::None, TextAlignment::Left, title);
+    window.set_default_foreground(colors::LIGHT_GREY);
+    window.print_ex(0, SCREEN_HEIGHT - 1, BackgroundFlag::None, TextAlignment::Left,
+      "Up/Down, PgUp/PgDn, Home/End: scroll   /: search   Esc: close");
+
+    let mut y = HISTORY_HEIGHT + 1;
+    for message in shown.iter().rev().skip(scroll) {
+      let msg = message.display();
+      y -= window.get_height_rect(0, 0, SCREEN_WIDTH, 0, &msg);
+      if y < 1 {
+        break;
+      }
+      window.set_default_foreground(tcod_color(message.color));
+      window.print_rect(0, y, SCREEN_WIDTH, 0, &msg);
+    }
+
+    blit(&window, (0, 0), (SCREEN_WIDTH, SCREEN_HEIGHT), root, (0, 0), 1.0, 1.0);
+    root.flush();
+
+    let key = root.wait_for_keypress(true);
+    let last = shown.len().saturating_sub(1);
+    match key {
+      Key { code: Up, .. } | Key { code: NumPad8, .. } => scroll = cmp::min(scroll + 1, last),
+      Key { code: Down, .. } | Key { code: NumPad2, .. } => scroll = scroll.saturating_sub(1),
+      Key { code: PageUp, .. } => scroll = cmp::min(scroll + HISTORY_HEIGHT as usize, last),
+      Key { code: PageDown, .. } => scroll = scroll.saturating_sub(HISTORY_HEIGHT as usize),
+      Key { code: Home, .. } => scroll = last,
+      Key { code: End, .. } => scroll = 0,
+      Key { printable: '/', .. } => {
+        let default = search.clone().unwrap_or_default();
+        // Escape out of the prompt goes back to every message
+        search = text_input("Search the messages for:", &default, MAX_SEARCH_LENGTH, searchable_char, SLOT_MENU_WIDTH, root);
+        scroll = 0;
+      }
+      Key { code: Escape, .. } | Key { printable: 'm', .. } => break,
+      _ => {}
+    }
+  }
 }
 
+fn searchable_char(c: char) -> bool {
+  !c.is_control()
+}
 
 fn msgbox(text: &str, width: i32, root: &mut Root) {
   let options: &[&str] = &[];
   menu(text, options, width, root);
 }
 
+// Asks for a line of text, starting from `default`, and returns it once Enter
+// is pressed with something acceptable typed in. Escape gives up.
+fn text_input(prompt: &str, default: &str, max_length: usize, valid: fn(char) -> bool, width: i32, root: &mut Root) -> Option<String> {
+  use tcod::input::KeyCode::{Backspace, Enter, Escape};
+
+  let mut text = String::from(default);
+  loop {
+    let prompt_height = root.get_height_rect(0, 0, width, SCREEN_HEIGHT, prompt);
+    let height = prompt_height + 2;
+    let mut window = Offscreen::new(width, height);
+    window.set_default_foreground(colors::WHITE);
+    window.print_rect_ex(0, 0, width, prompt_height, BackgroundFlag::None, TextAlignment::Left, prompt);
+    window.print_ex(0, prompt_height + 1, BackgroundFlag::None, TextAlignment::Left, format!("> {}_", text));
+
+    let x = SCREEN_WIDTH / 2 - width / 2;
+    let y = SCREEN_HEIGHT / 2 - height / 2;
+    tcod::console::blit(&window, (0, 0), (width, height), root, (x, y), 1.0, 0.7);
+    root.flush();
+
+    let key = root.wait_for_keypress(true);
+    match key.code {
+      Enter if !text.trim().is_empty() => return Some(text.trim().to_string()),
+      Escape => return None,
+      Backspace => {
+        text.pop();
+      }
+      _ if valid(key.printable) && text.len() < max_length => text.push(key.printable),
+      _ => {}
+    }
+  }
+}
+
+fn confirm(question: &str, width: i32, root: &mut Root) -> bool {
+  menu(question, &["Yes", "No"], width, root) == Some(0)
+}
+
+// The first "game N" that isn't already taken.
+fn unused_slot_name() -> String {
+  (1..).map(|n| format!("game {}", n)).find(|name| !slot_exists(name)).unwrap()
+}
+
+fn new_slot(root: &mut Root) -> Option<String> {
+  loop {
+    let name = text_input("Name this game's save (letters, digits, spaces, - and _), or Escape to go back:",
+      &unused_slot_name(), MAX_SLOT_NAME_LENGTH, valid_slot_char, SLOT_MENU_WIDTH, root)?;
+    if !valid_slot_name(&name) {
+      msgbox(&format!("\n\"{}\" can't be used as a save name.\n", name), SLOT_MENU_WIDTH, root);
+      continue;
+    }
+    if !slot_exists(&name) || confirm(&format!("There is already a save called \"{}\". Replace it?\n", name), SLOT_MENU_WIDTH, root) {
+      return Some(name);
+    }
+  }
+}
+
+fn describe_slot(slot: &Slot) -> String {
+  match slot.info {
+    Some(ref info) => {
+      let saved_at = if info.saved_at == 0 { "unknown time".into() } else { format_timestamp(info.saved_at) };
+      format!("{} - level {}, depth {}, turn {}, {}", slot.name, info.character_level, info.dungeon_level, info.turn, saved_at)
+    }
+    None => format!("{} - can't be read", slot.name),
+  }
+}
+
+fn describe_replay(replay: &ReplayFile) -> String {
+  if replay.started_at == 0 {
+    replay.slot.clone()
+  } else {
+    format!("{} - started {}", replay.slot, format_timestamp(replay.started_at))
+  }
+}
 
-fn main_menu(tcod: &mut Tcod) {
+// Lists the saves to pick one to load, or to delete. Returns the loaded game
+// and the slot it came from.
+fn load_menu(tcod: &mut Tcod, defs: &Definitions) -> Option<(String, GameState)> {
+  loop {
+    let slots = list_slots();
+    if slots.is_empty() {
+      msgbox("\nNo saved games to load.\n", 24, &mut tcod.root);
+      return None;
+    }
+    let options: Vec<String> = slots.iter().map(describe_slot).collect();
+    tcod.root.clear();
+    let slot = &slots[paged_menu("Pick a saved game, or any other key to go back.\n", &options, SLOT_MENU_WIDTH, &mut tcod.root)?];
+
+    match menu(&format!("{}\n", slot.name), &["Load", "Delete", "Back"], 24, &mut tcod.root) {
+      Some(0) => match load_game(&slot.name, defs.clone()) {
+        Ok(state) => return Some((slot.name.clone(), state)),
+        Err(e) => msgbox(&format!("\n{}\n", e), ERROR_BOX_WIDTH, &mut tcod.root),
+      },
+      Some(1) => {
+        if confirm(&format!("Delete \"{}\" for good?\n", slot.name), SLOT_MENU_WIDTH, &mut tcod.root) {
+          if let Err(e) = delete_slot(&slot.name) {
+            msgbox(&format!("\n{}\n", e), ERROR_BOX_WIDTH, &mut tcod.root);
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+
+fn main_menu(tcod: &mut Tcod, seed: Option<u64>, message_limit: usize, defs: &Definitions) {
   let img = tcod::image::Image::from_file("menu_background.png")
     .ok().expect("Background image not found");
 
@@ -1110,27 +969,44 @@ fn main_menu(tcod: &mut Tcod) {
     tcod.root.print_ex(SCREEN_WIDTH/2, SCREEN_HEIGHT/2 - 3, BackgroundFlag::None, TextAlignment::Center, "~ a  tutorial ~");
 
 
-    let choices = &["Play a new game", "Continue last game", "Quit"];
+    let choices = &["Play a new game", "Load a saved game", "Watch replay", "High scores", "Quit"];
     let choice = menu("", choices, 24, &mut tcod.root);
 
     match choice {
       Some(0) => {
-        let (mut objects, mut game) = new_game(tcod);
-        play_game(&mut objects, &mut game, tcod);
+        if let Some(slot) = new_slot(&mut tcod.root) {
+          let mut state = new_game(seed.unwrap_or_else(GameRng::random_seed), defs.clone());
+          state.game.log.set_limit(message_limit);
+          tcod.con.clear();
+          play_game(&mut state, &slot, tcod);
+        }
       },
       Some(1) => {
-        match load_game() {
-          Ok((mut objects, mut game)) => {
-            initialise_fov(&game.map, tcod);
-            play_game(&mut objects, &mut game, tcod);
-          }
-          Err(_e) => {
-            msgbox("\nNo saved game to load.\n", 24, &mut tcod.root);
-            continue;
-          }
+        if let Some((slot, mut state)) = load_menu(tcod, defs) {
+          state.game.log.set_limit(message_limit);
+          tcod.con.clear();
+          play_game(&mut state, &slot, tcod);
         }
       },
       Some(2) => {
+        let replays = list_replays();
+        if replays.is_empty() {
+          msgbox("\nNo replays to watch.\n", 24, &mut tcod.root);
+          continue;
+        }
+        tcod.root.clear();
+        let options: Vec<String> = replays.iter().map(describe_replay).collect();
+        let replay = match paged_menu("Pick a game to watch, or any other key to go back.\n", &options, SLOT_MENU_WIDTH, &mut tcod.root) {
+          Some(index) => &replays[index],
+          None => continue,
+        };
+        match Replay::load(&replay.path).and_then(|replay| replay.check_definitions(defs).map(|()| replay)) {
+          Ok(replay) => watch_replay(tcod, &replay, defs),
+          Err(e) => msgbox(&format!("\nCan't watch the replay: {}.\n", e), ERROR_BOX_WIDTH, &mut tcod.root),
+        }
+      },
+      Some(3) => show_high_scores(None, &mut tcod.root),
+      Some(4) => {
         break;
       },
       _ => {}
@@ -1139,101 +1015,268 @@ fn main_menu(tcod: &mut Tcod) {
 }
 
 
-fn new_game(tcod: &mut Tcod) -> (Vec<Object>, Game) {
-  let mut player = Object::new(0, 0, '%', "player", colors::WHITE, true);
-  player.alive = true;
-  player.fighter = Some( Fighter {
-    max_hp: 100,
-    hp: 100,
-    defense: 1,
-    power: 4,
-    xp: 0,
-    on_death: DeathCallback::Player,
-  });
-  let mut objects = vec![player];
-  let mut game = Game {
-    map: make_map(&mut objects, 1),
-    log: vec![],
-    inventory: vec![],
-    dungeon_level: 1,
-  };
-
-  initialise_fov(&game.map, tcod);
-
-  game.log.add("Welcome stranger! Prepare to perish in the Tombs of The Glass Oak.", colors::RED);
-
-  (objects, game)
+// Writes out the replay of the run so far, returning how many actions are in the file.
+fn start_replay(state: &GameState, slot: &str) -> usize {
+  match state.game.replay {
+    Some(ref replay) if replay.write(replay_path(slot, replay.started_at)).is_ok() => replay.actions.len(),
+    _ => 0,
+  }
 }
 
-
-fn initialise_fov(map: &Map, tcod: &mut Tcod) {
-  for y in 0..MAP_HEIGHT {
-    for x in 0..MAP_WIDTH {
-      tcod.fov.set(
-        x,
-        y,
-        !map[x as usize][y as usize].block_sight,
-        !map[x as usize][y as usize].blocked
-      );
+// Appends any actions taken since the last call, so the file is current if the game crashes.
+fn record_replay(state: &GameState, slot: &str, written: &mut usize) {
+  if let Some(ref replay) = state.game.replay {
+    if replay.actions.len() > *written && replay.append(replay_path(slot, replay.started_at), *written).is_ok() {
+      *written = replay.actions.len();
     }
   }
-  tcod.con.clear();
 }
 
 
-fn next_level(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
-  game.log.add("You take a moment to rest, and recover your strength", colors::VIOLET);
-  let heal_hp = objects[PLAYER].fighter.map_or(0, |f| f.max_hp / 2);
-  objects[PLAYER].heal(heal_hp);
+fn watch_replay(tcod: &mut Tcod, replay: &Replay, defs: &Definitions) {
+  use tcod::input::KeyCode::Escape;
+
+  let mut state = new_game(replay.seed, defs.clone());
+  tcod.con.clear();
+  render_all(tcod, &state, true);
+
+  for action in &replay.actions {
+    if tcod.root.window_closed() {
+      return;
+    }
+    if let Some((_, Event::Key(key))) = input::check_for_event(input::KEY_PRESS) {
+      if key.code == Escape {
+        return;
+      }
+    }
 
-  game.log.add("After a rare moment of peace, you descend deeper into \
-    the heart of the dungeon...", colors::RED);
-  game.dungeon_level += 1;
-  game.map = make_map(objects, game.dungeon_level);
-  initialise_fov(&game.map, tcod);
+    for object in state.objects.iter() {
+      object.clear(&mut tcod.con);
+    }
+    let events = state.step(*action);
+    if changed_level(&events) {
+      tcod.con.clear();
+    }
+    animate_projectiles(tcod, &state, &events);
+    render_all(tcod, &state, true);
+    tcod.root.flush();
+  }
+  msgbox("\nEnd of replay.\n", 24, &mut tcod.root);
 }
 
 
-fn play_game(objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) {
+fn play_game(state: &mut GameState, slot: &str, tcod: &mut Tcod) {
   let mut previous_player_position = (-1, -1);
-  let mut key = Default::default();
+  let mut replay_written = start_replay(state, slot);
+  let mut buried = !state.player().alive;
+  let mut place = None;
 
   while !tcod.root.window_closed() {
-    match input::check_for_event(input::MOUSE | input::KEY_PRESS) {
-      Some((_, Event::Mouse(m))) => tcod.mouse = m,
-      Some((_, Event::Key(k))) => key = k,
-      _ => key = Default::default(),
-    }
+    let mut clicked = None;
+    let key = match input::check_for_event(input::MOUSE | input::KEY_PRESS) {
+      Some((_, Event::Mouse(m))) => {
+        tcod.mouse = m;
+        if m.lbutton_pressed && (m.cx as i32) < MAP_WIDTH && (m.cy as i32) < MAP_HEIGHT {
+          clicked = Some((m.cx as i32, m.cy as i32));
+          // used up here, so a cursor opened later doesn't take it as a click
+          tcod.mouse.lbutton_pressed = false;
+        }
+        Default::default()
+      }
+      Some((_, Event::Key(k))) => k,
+      _ => Default::default(),
+    };
 
-    let fov_recompute = previous_player_position != (objects[PLAYER].pos());
-    render_all(tcod, &objects, game, fov_recompute);
+    let fov_recompute = previous_player_position != (state.player().pos());
+    render_all(tcod, state, fov_recompute);
 
     tcod.root.flush();
-    level_up(objects, game, tcod);
+    if state.level_up_pending() {
+      level_up(tcod, state);
+    }
 
-    for object in objects.iter_mut() {
+    for object in state.objects.iter() {
       object.clear(&mut tcod.con);
     }
 
-    previous_player_position = objects[PLAYER].pos();
-    let player_action = handle_keys(key, tcod, objects, game);
+    previous_player_position = state.player().pos();
+    let player = state.player();
+    let player_action = if player.alive && player.has_status(StatusKind::Paralyzed) {
+      player_step(tcod, state, Action::Wait)
+    } else if let (Some(to), true) = (clicked, player.alive) {
+      travel_to(tcod, state, to)
+    } else {
+      handle_keys(key, tcod, state)
+    };
+    record_replay(state, slot, &mut replay_written);
+    if !state.player().alive && !buried {
+      place = bury(state, slot);
+      buried = true;
+    }
     if player_action == PlayerAction::Exit {
-      save_game(objects, game).unwrap();
-      break
+      if buried {
+        // there's nothing left to save, only the scores to see
+        show_high_scores(place, &mut tcod.root);
+        break;
+      }
+      match save_game(slot, &state.objects, &state.game) {
+        Ok(()) => break,
+        Err(e) => {
+          let msg = format!("\n{}\n\nYour game was not saved. Press any key to go back to it.\n", e);
+          msgbox(&msg, ERROR_BOX_WIDTH, &mut tcod.root);
+        }
+      }
     }
+  }
+}
 
-    if objects[PLAYER].alive && player_action != PlayerAction::DidntTakeTurn {
-      for id in 0..objects.len() {
-        if objects[id].ai.is_some() {
-          ai_take_turn(id, objects, &tcod.fov, game);
-        }
+
+// Permadeath: a dead character's save goes away for good, and what's left of
+// the run is written up in the morgue and scored. Returns where the run placed
+// on the high-score table, if it made it on.
+fn bury(state: &mut GameState, slot: &str) -> Option<usize> {
+  match delete_slot(slot) {
+    Ok(()) | Err(SaveError::Missing) => {}
+    Err(e) => state.game.log.add(format!("Couldn't delete the save: {}", e), game_colors::RED),
+  }
+  match write_morgue(state, slot) {
+    Ok(path) => state.game.log.add(format!("Your story was written to {}.", path.display()), game_colors::LIGHT_GREY),
+    Err(e) => state.game.log.add(format!("Couldn't write the morgue file: {}", e), game_colors::RED),
+  }
+  match record_high_score(HighScore::new(state, slot, unix_time())) {
+    Ok(place) => place,
+    Err(e) => {
+      state.game.log.add(format!("Couldn't record your score: {}", e), game_colors::RED);
+      None
+    }
+  }
+}
+
+// The high-score table, with the run that placed `highlight` marked.
+fn show_high_scores(highlight: Option<usize>, root: &mut Root) {
+  let text = match load_high_scores() {
+    Ok(ref scores) if scores.is_empty() => "\nNo one has died yet.\n".to_string(),
+    Ok(scores) => {
+      let mut text = String::from("High scores\n\n");
+      for (place, score) in scores.iter().enumerate() {
+        let marker = if Some(place) == highlight { '>' } else { ' ' };
+        let cause = score.cause_of_death.as_ref().map_or("something unknown", |cause| cause.as_str());
+        text.push_str(&format!("{}{:2}. {:6}  {}: level {}, depth {}, {} kills, killed by {}\n",
+          marker, place + 1, score.score, score.name, score.character_level, score.deepest_level, score.kills, cause));
+      }
+      text
+    }
+    Err(e) => format!("\n{}\n", e),
+  };
+  msgbox(&text, HIGH_SCORES_WIDTH, root);
+}
+
+
+fn arg_value(name: &str) -> Option<String> {
+  let args: Vec<String> = env::args().collect();
+  args.iter().position(|arg| arg == name).map(|index| {
+    args.get(index + 1).cloned().unwrap_or_else(|| {
+      eprintln!("{} needs a value", name);
+      process::exit(2);
+    })
+  })
+}
+
+
+// Reads the key bindings from `--keymap`, which can name one of the presets
+// or a keymap file. Without it the keymap file in the data directory is used,
+// or the default preset if there isn't one.
+fn load_keymap() -> Keymap {
+  let path = match arg_value("--keymap") {
+    Some(ref name) if Keymap::is_preset(name) => return Keymap::preset(name).unwrap(),
+    Some(path) => path,
+    None => {
+      let path = data_dir().join(KEYMAP_FILE);
+      if !path.exists() {
+        return Keymap::default();
       }
+      path.to_string_lossy().into_owned()
     }
-  }  
+  };
+  Keymap::load(&path).unwrap_or_else(|e| {
+    eprintln!("Invalid keymap: {}", e);
+    process::exit(1);
+  })
+}
+
+
+// Reads the monster and item definitions, from `--defs` if given. Without it
+// the file next to the game is used, or the built-in copy if there isn't one.
+fn load_definitions() -> Definitions {
+  let path = match arg_value("--defs") {
+    Some(path) => path,
+    None if Path::new(DEFINITIONS_FILE).exists() => DEFINITIONS_FILE.into(),
+    None => return Definitions::default(),
+  };
+  Definitions::load(&path).unwrap_or_else(|e| {
+    eprintln!("Invalid definitions: {}", e);
+    process::exit(1);
+  })
+}
+
+
+// Re-simulates a replay file without opening a window and prints where the run ended up.
+fn report_replay(path: &str, defs: &Definitions) {
+  let replay = Replay::load(path).unwrap_or_else(|e| {
+    eprintln!("Could not read replay {}: {}", path, e);
+    process::exit(1);
+  });
+  if replay.version != GAME_VERSION {
+    println!("Warning: replay was recorded with version {}, this is {}.", replay.version, GAME_VERSION);
+  }
+
+  let state = replay.simulate(defs).unwrap_or_else(|e| {
+    eprintln!("Could not play replay {}: {}", path, e);
+    process::exit(1);
+  });
+  let player = state.player();
+  println!("Seed: {}", replay.seed);
+  println!("Actions: {}", replay.actions.len());
+  println!("Dungeon level: {}", state.game.dungeon_level);
+  println!("Character level: {}", player.level);
+  if let Some(fighter) = player.fighter {
+    println!("HP: {}/{}", fighter.hp, player.max_hp(&state.game));
+    println!("Experience: {}", fighter.xp);
+  }
+  println!("Alive: {}", player.alive);
+  println!("Position: {:?}", player.pos());
+  let inventory: Vec<_> = state.game.inventory.iter().map(|item| item.name.clone()).collect();
+  println!("Inventory: {}", inventory.join(", "));
+  println!("Last messages:");
+  let skip = state.game.log.len().saturating_sub(MSG_HEIGHT);
+  for message in state.game.log.iter().skip(skip) {
+    println!("  {}", message.display());
+  }
 }
 
 
 fn main() {
+  let defs = load_definitions();
+  let keymap = load_keymap();
+  if let Some(path) = arg_value("--replay") {
+    report_replay(&path, &defs);
+    return;
+  }
+  let seed = arg_value("--seed").map(|seed| {
+    seed.parse().unwrap_or_else(|_| {
+      eprintln!("--seed needs a number, e.g. --seed 1234");
+      process::exit(2);
+    })
+  });
+  let message_limit = arg_value("--message-limit").map_or(DEFAULT_MESSAGE_LIMIT, |limit| {
+    match limit.parse() {
+      Ok(limit) if limit > 0 => limit,
+      _ => {
+        eprintln!("--message-limit needs a number above 0, e.g. --message-limit 1000");
+        process::exit(2);
+      }
+    }
+  });
   let root = Root::initializer()
     .font("square10x10.png", FontLayout::Tcod)
     .font_type(FontType::Greyscale)
@@ -1246,9 +1289,13 @@ fn main() {
     root: root,
     con: Offscreen::new(MAP_WIDTH, MAP_HEIGHT),
     panel: Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT),
-    fov: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
     mouse: Default::default(),
+    keymap,
+    auto_pickup: !env::args().any(|arg| arg == "--no-autopickup"),
   };
 
-  main_menu(&mut tcod);
+  if let Err(e) = import_old_save() {
+    eprintln!("Could not move the old savegame into {}: {}", save_dir().display(), e);
+  }
+  main_menu(&mut tcod, seed, message_limit, &defs);
 }
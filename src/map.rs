@@ -0,0 +1,502 @@
+use rand::Rng;
+use rand::distributions::{Weighted, WeightedChoice, IndependentSample};
+use std::cmp;
+
+use colors;
+use definitions::Definitions;
+use game::PLAYER;
+use object::{DeathCallback, Fighter, Object, Stairs};
+use pathfinding::DIRECTIONS;
+use rng::GameRng;
+
+pub const MAP_WIDTH: i32 = 80;
+pub const MAP_HEIGHT: i32 = 43;
+const ROOM_MAX_SIZE: i32 = 10;
+const ROOM_MIN_SIZE: i32 = 6;
+const MAX_ROOMS: i32 = 30;
+const BSP_MAX_DEPTH: u32 = 5;
+// big enough for the smallest room plus a wall on either side
+const BSP_MIN_AREA_SIZE: i32 = ROOM_MIN_SIZE + 2;
+const CAVE_WALL_CHANCE: u32 = 45;
+const CAVE_SMOOTHING_STEPS: u32 = 5;
+// caves smaller than this are thrown away and dug again
+const CAVE_MIN_FLOOR: usize = (MAP_WIDTH * MAP_HEIGHT / 4) as usize;
+const CAVE_MIN_SECTOR_FLOOR: usize = (ROOM_MIN_SIZE * ROOM_MIN_SIZE / 2) as usize;
+
+pub type Map = Vec<Vec<Tile>>;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Tile {
+  pub blocked: bool,
+  pub block_sight: bool,
+  pub explored: bool,
+}
+
+impl Tile {
+  pub fn empty () -> Self {
+    Tile { blocked: false, explored: false, block_sight: false }
+  }
+
+  pub fn wall () -> Self {
+    Tile { blocked: true, explored: false, block_sight: true }
+  }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rect {
+  pub x1: i32,
+  pub y1: i32,
+  pub x2: i32,
+  pub y2: i32,
+}
+
+impl Rect {
+  pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+    Rect { x1: x, y1: y, x2: x + w, y2: y + h }
+  }
+
+  pub fn center(&self) -> (i32, i32) {
+    let center_x = (self.x1+self.x2)/2;
+    let center_y = (self.y1+self.y2)/2;
+    (center_x, center_y)
+  }
+
+  pub fn intersects_with(&self, other: &Rect) -> bool {
+    (self.x1 <= other.x2) && (self.x2 >= other.x1) &&
+      (self.y1 <= other.y2) && (self.y2 >= other.y1)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transition {
+  pub level: u32,
+  pub value: u32,
+}
+
+pub fn from_dungeon_level(table: &[Transition], level: u32) -> u32 {
+  table.iter()
+    .rev()
+    .find(|transition| level >= transition.level)
+    .map_or(0, |transition| transition.value)
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapGenerator {
+  // the tutorial's rooms: random rectangles, skipping any that overlap
+  Rooms,
+  // binary space partition: the whole map is split up and every part gets a room
+  Bsp,
+  // cellular automata caverns, with no rooms at all
+  Caves,
+}
+
+fn choose_generator(level: u32, rng: &mut GameRng) -> MapGenerator {
+  let generator_chances = &mut [
+    Weighted {weight: from_dungeon_level(&[
+      Transition {level: 1, value: 100},
+      Transition {level: 2, value: 50},
+      Transition {level: 4, value: 30},
+    ], level), item: MapGenerator::Rooms},
+    Weighted {weight: from_dungeon_level(&[
+      Transition {level: 2, value: 50},
+      Transition {level: 4, value: 40},
+    ], level), item: MapGenerator::Bsp},
+    Weighted {weight: from_dungeon_level(&[
+      Transition {level: 3, value: 25},
+      Transition {level: 4, value: 30},
+    ], level), item: MapGenerator::Caves},
+  ];
+  WeightedChoice::new(generator_chances).ind_sample(rng)
+}
+
+
+pub fn make_map(objects: &mut Vec<Object>, level: u32, defs: &Definitions, rng: &mut GameRng) -> Map {
+  let generator = choose_generator(level, rng);
+  make_map_with(generator, objects, level, defs, rng)
+}
+
+pub fn make_map_with(generator: MapGenerator, objects: &mut Vec<Object>, level: u32, defs: &Definitions, rng: &mut GameRng) -> Map {
+  // fills map with unblocked tiles... odd macro syntax!
+  let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+  assert_eq!(&objects[PLAYER] as *const _, &objects[0] as *const _);
+  objects.truncate(1);
+
+  let stairs_pos = match generator {
+    MapGenerator::Rooms | MapGenerator::Bsp => {
+      let rooms = if generator == MapGenerator::Rooms {
+        random_rooms(&mut map, rng)
+      } else {
+        let whole_map = Rect { x1: 0, y1: 0, x2: MAP_WIDTH - 1, y2: MAP_HEIGHT - 1 };
+        bsp_rooms(whole_map, BSP_MAX_DEPTH, &mut map, rng)
+      };
+
+      let (first_room_x, first_room_y) = rooms[0].center();
+      objects[PLAYER].set_pos(first_room_x, first_room_y);
+      for room in &rooms {
+        place_objects(*room, &map, objects, level, defs, rng);
+      }
+      rooms[rooms.len() - 1].center()
+    }
+    MapGenerator::Caves => {
+      let floor = carve_caves(&mut map, rng);
+      let start = floor[rng.gen_range(0, floor.len())];
+      objects[PLAYER].set_pos(start.0, start.1);
+
+      // spawn about as much per cave area as a room of the same size would get
+      for sector in cave_sectors(&floor) {
+        place_objects_in(&sector, &map, objects, level, defs, rng);
+      }
+
+      // the stairs go as far into the cave as possible
+      let reachable = flood_fill(&map, start);
+      reachable[reachable.len() - 1].0
+    }
+  };
+
+  if level > 1 {
+    let (start_x, start_y) = objects[PLAYER].pos();
+    let mut up_stairs = Object::new(start_x, start_y, '<', "up stairs", colors::WHITE, false);
+    up_stairs.always_visible = true;
+    up_stairs.stairs = Some(Stairs::Up);
+    objects.push(up_stairs);
+  }
+
+  let mut stairs = Object::new(stairs_pos.0, stairs_pos.1, '>', "down stairs", colors::WHITE, false);
+  stairs.always_visible = true;
+  stairs.stairs = Some(Stairs::Down);
+  objects.push(stairs);
+
+  map
+}
+
+fn random_rooms(map: &mut Map, rng: &mut GameRng) -> Vec<Rect> {
+  let mut rooms: Vec<Rect> = vec![];
+
+  for _ in 0..MAX_ROOMS {
+    let w = rng.gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+    let h = rng.gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+
+    let x = rng.gen_range(0, MAP_WIDTH - w);
+    let y = rng.gen_range(0, MAP_HEIGHT - h);
+
+    let new_room = Rect::new(x, y, w, h);
+    let failed = rooms.iter().any(|other_room| new_room.intersects_with(other_room));
+
+    if !failed {
+      create_room(new_room, map);
+      if let Some(prev_room) = rooms.last() {
+        connect_rooms(*prev_room, new_room, map, rng);
+      }
+      rooms.push(new_room);
+    }
+  }
+  rooms
+}
+
+// Splits `area` in two along its longer side until the parts are too small or
+// `depth` runs out, puts a room somewhere in each part, and joins the two halves
+// of every split with a tunnel. Rooms come back in left-to-right, top-to-bottom
+// order, so the first and last are usually far apart.
+fn bsp_rooms(area: Rect, depth: u32, map: &mut Map, rng: &mut GameRng) -> Vec<Rect> {
+  let width = area.x2 - area.x1;
+  let height = area.y2 - area.y1;
+  let can_split_x = width >= 2 * BSP_MIN_AREA_SIZE;
+  let can_split_y = height >= 2 * BSP_MIN_AREA_SIZE;
+
+  if depth == 0 || !(can_split_x || can_split_y) {
+    let w = rng.gen_range(ROOM_MIN_SIZE, cmp::min(ROOM_MAX_SIZE, width) + 1);
+    let h = rng.gen_range(ROOM_MIN_SIZE, cmp::min(ROOM_MAX_SIZE, height) + 1);
+    let x = rng.gen_range(area.x1, area.x2 - w + 1);
+    let y = rng.gen_range(area.y1, area.y2 - h + 1);
+    let room = Rect::new(x, y, w, h);
+    create_room(room, map);
+    return vec![room];
+  }
+
+  let split_x = if can_split_x && can_split_y {
+    if width * 4 > height * 5 {
+      true
+    } else if height * 4 > width * 5 {
+      false
+    } else {
+      rng.gen()
+    }
+  } else {
+    can_split_x
+  };
+
+  let (first, second) = if split_x {
+    let x = rng.gen_range(area.x1 + BSP_MIN_AREA_SIZE, area.x2 - BSP_MIN_AREA_SIZE + 1);
+    (Rect { x2: x, ..area }, Rect { x1: x, ..area })
+  } else {
+    let y = rng.gen_range(area.y1 + BSP_MIN_AREA_SIZE, area.y2 - BSP_MIN_AREA_SIZE + 1);
+    (Rect { y2: y, ..area }, Rect { y1: y, ..area })
+  };
+
+  let mut rooms = bsp_rooms(first, depth - 1, map, rng);
+  let second_rooms = bsp_rooms(second, depth - 1, map, rng);
+  connect_rooms(rooms[rooms.len() - 1], second_rooms[0], map, rng);
+  rooms.extend(second_rooms);
+  rooms
+}
+
+// Random noise smoothed into caverns: each step, a cell becomes wall if most of
+// the 3x3 block around it is wall. Only the biggest connected cavern is kept,
+// and its floor cells are returned.
+fn carve_caves(map: &mut Map, rng: &mut GameRng) -> Vec<(i32, i32)> {
+  loop {
+    for x in 1..(MAP_WIDTH - 1) {
+      for y in 1..(MAP_HEIGHT - 1) {
+        map[x as usize][y as usize] = if rng.gen_range(0, 100) < CAVE_WALL_CHANCE {
+          Tile::wall()
+        } else {
+          Tile::empty()
+        };
+      }
+    }
+
+    for _ in 0..CAVE_SMOOTHING_STEPS {
+      let previous = map.clone();
+      for x in 1..(MAP_WIDTH - 1) {
+        for y in 1..(MAP_HEIGHT - 1) {
+          let mut walls = 0;
+          for nx in (x - 1)..(x + 2) {
+            for ny in (y - 1)..(y + 2) {
+              if previous[nx as usize][ny as usize].blocked {
+                walls += 1;
+              }
+            }
+          }
+          map[x as usize][y as usize] = if walls >= 5 { Tile::wall() } else { Tile::empty() };
+        }
+      }
+    }
+
+    let mut seen = vec![vec![false; MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+    let mut largest: Vec<(i32, i32)> = vec![];
+    for x in 0..MAP_WIDTH {
+      for y in 0..MAP_HEIGHT {
+        if !map[x as usize][y as usize].blocked && !seen[x as usize][y as usize] {
+          let region: Vec<(i32, i32)> = flood_fill(map, (x, y)).into_iter().map(|(pos, _)| pos).collect();
+          for &(rx, ry) in &region {
+            seen[rx as usize][ry as usize] = true;
+          }
+          if region.len() > largest.len() {
+            largest = region;
+          }
+        }
+      }
+    }
+
+    if largest.len() >= CAVE_MIN_FLOOR {
+      for x in 0..MAP_WIDTH {
+        for y in 0..MAP_HEIGHT {
+          map[x as usize][y as usize] = Tile::wall();
+        }
+      }
+      for &(x, y) in &largest {
+        map[x as usize][y as usize] = Tile::empty();
+      }
+      return largest;
+    }
+  }
+}
+
+// Groups floor cells by which room-sized square of the map they're in, leaving
+// out squares with too little floor to count as a room.
+fn cave_sectors(floor: &[(i32, i32)]) -> Vec<Vec<(i32, i32)>> {
+  let columns = (MAP_WIDTH + ROOM_MAX_SIZE - 1) / ROOM_MAX_SIZE;
+  let rows = (MAP_HEIGHT + ROOM_MAX_SIZE - 1) / ROOM_MAX_SIZE;
+  let mut sectors = vec![vec![]; (columns * rows) as usize];
+  for &(x, y) in floor {
+    sectors[(y / ROOM_MAX_SIZE * columns + x / ROOM_MAX_SIZE) as usize].push((x, y));
+  }
+  sectors.into_iter().filter(|sector| sector.len() >= CAVE_MIN_SECTOR_FLOOR).collect()
+}
+
+// Every unblocked tile reachable from `start`, with its distance in steps,
+// nearest first.
+fn flood_fill(map: &Map, start: (i32, i32)) -> Vec<((i32, i32), u32)> {
+  let mut seen = vec![vec![false; MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+  let mut reached = vec![(start, 0)];
+  seen[start.0 as usize][start.1 as usize] = true;
+
+  let mut next = 0;
+  while next < reached.len() {
+    let ((x, y), distance) = reached[next];
+    next += 1;
+    for &(dx, dy) in DIRECTIONS.iter() {
+      let (nx, ny) = (x + dx, y + dy);
+      if nx < 0 || ny < 0 || nx >= MAP_WIDTH || ny >= MAP_HEIGHT {
+        continue;
+      }
+      if !seen[nx as usize][ny as usize] && !map[nx as usize][ny as usize].blocked {
+        seen[nx as usize][ny as usize] = true;
+        reached.push(((nx, ny), distance + 1));
+      }
+    }
+  }
+  reached
+}
+
+fn connect_rooms(from: Rect, to: Rect, map: &mut Map, rng: &mut GameRng) {
+  let (prev_x, prev_y) = from.center();
+  let (new_x, new_y) = to.center();
+
+  if rng.gen() {
+    create_h_tunnel(prev_x, new_x, prev_y, map);
+    create_v_tunnel(prev_y, new_y, new_x, map);
+  } else {
+    create_v_tunnel(prev_y, new_y, prev_x, map);
+    create_h_tunnel(prev_x, new_x, new_y, map);
+  }
+}
+
+pub fn create_room(room: Rect, map: &mut Map) {
+  for x in (room.x1 + 1) .. room.x2 {
+    for y in (room.y1 + 1) .. room.y2 {
+      map[x as usize][y as usize] = Tile::empty();
+    }
+  }
+}
+
+pub fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
+  for x in cmp::min(x1, x2)..(cmp::max(x1, x2)+1) {
+    map[x as usize][y as usize] = Tile::empty();
+  }
+}
+
+pub fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
+  for y in cmp::min(y1, y2)..(cmp::max(y1, y2)+1) {
+    map[x as usize][y as usize] = Tile::empty();
+  }
+}
+
+
+pub fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, level: u32, defs: &Definitions, rng: &mut GameRng) {
+  let mut cells = vec![];
+  for x in (room.x1 + 1) .. room.x2 {
+    for y in (room.y1 + 1) .. room.y2 {
+      cells.push((x, y));
+    }
+  }
+  place_objects_in(&cells, map, objects, level, defs, rng);
+}
+
+// Spawns one room's worth of monsters and items on the given floor cells,
+// picking each one by its spawn weight for this level.
+pub fn place_objects_in(cells: &[(i32, i32)], map: &Map, objects: &mut Vec<Object>, level: u32, defs: &Definitions, rng: &mut GameRng) {
+  let max_monsters = from_dungeon_level(&defs.max_monsters_per_room, level);
+  let num_creatures = rng.gen_range(0, max_monsters + 1);
+
+  for _ in 0..num_creatures {
+    let (x, y) = cells[rng.gen_range(0, cells.len())];
+
+    if !is_blocked(x, y, map, objects) {
+      let monster_chances = &mut defs.monsters.iter().enumerate().map(|(index, monster)| {
+        Weighted {weight: from_dungeon_level(&monster.spawn, level), item: index}
+      }).collect::<Vec<_>>();
+      let monster_choice = WeightedChoice::new(monster_chances);
+      let def = &defs.monsters[monster_choice.ind_sample(rng)];
+      let mut creature = Object::new(x, y, def.glyph, &format!("{}-{}", def.name, x+y), def.color, true);
+      creature.fighter = Some( Fighter {
+        max_hp: def.fighter.max_hp,
+        hp: def.fighter.max_hp,
+        defense: def.fighter.defense,
+        power: def.fighter.power,
+        xp: def.fighter.xp,
+        on_death: DeathCallback::Monster,
+        on_hit: def.fighter.on_hit,
+        speed: def.fighter.speed,
+        vision: def.fighter.vision,
+        flee_below: def.fighter.flee_below,
+        ranged: def.fighter.ranged,
+      });
+      creature.ai = Some(def.ai.clone());
+      creature.alive = true;
+      objects.push(creature);
+    }
+  }
+
+  let max_items = from_dungeon_level(&defs.max_items_per_room, level);
+  let num_items = rng.gen_range(0, max_items + 1);
+
+  for _ in 0..num_items {
+    let (x, y) = cells[rng.gen_range(0, cells.len())];
+
+    if !is_blocked(x, y, map, objects) {
+      let item_chances = &mut defs.items.iter().enumerate().map(|(index, item)| {
+        Weighted {weight: from_dungeon_level(&item.spawn, level), item: index}
+      }).collect::<Vec<_>>();
+      let item_choice = WeightedChoice::new(item_chances);
+      let def = &defs.items[item_choice.ind_sample(rng)];
+      let mut item = Object::new(x, y, def.glyph, &def.name, def.color, false);
+      item.item = Some(def.effect);
+      item.equipment = def.equipment;
+      item.always_visible = true;
+      objects.push(item);
+    }
+  }
+}
+
+
+// Off the edge of the map counts as blocked.
+pub fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
+  if !in_bounds(x, y) || map[x as usize][y as usize].blocked {
+    return true;
+  }
+
+  objects.iter().any(|object| {
+    object.blocks && object.pos() == (x, y)
+  })
+}
+
+pub fn in_bounds(x: i32, y: i32) -> bool {
+  x >= 0 && y >= 0 && x < MAP_WIDTH && y < MAP_HEIGHT
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SEEDS: u64 = 20;
+
+  // Makes levels with the generator from a run of seeds, and checks that
+  // everything on each one can be walked to from where the player starts.
+  fn assert_connected(generator: MapGenerator) {
+    let defs = Definitions::default();
+    for seed in 0..SEEDS {
+      let level = 2 + (seed % 5) as u32;
+      let mut rng = GameRng::new(seed);
+      let mut objects = vec![Object::new(0, 0, '@', "player", colors::WHITE, true)];
+      let map = make_map_with(generator, &mut objects, level, &defs, &mut rng);
+
+      let (x, y) = objects[PLAYER].pos();
+      assert!(!map[x as usize][y as usize].blocked, "{:?} seed {}: the player starts in a wall", generator, seed);
+      let reachable: Vec<(i32, i32)> = flood_fill(&map, (x, y)).into_iter().map(|(pos, _)| pos).collect();
+      for object in &objects {
+        assert!(reachable.contains(&object.pos()), "{:?} seed {}: can't reach {} at {:?}", generator, seed, object.name, object.pos());
+      }
+      for &kind in &[Stairs::Up, Stairs::Down] {
+        assert!(objects.iter().any(|object| object.stairs == Some(kind)), "{:?} seed {}: no {:?} stairs", generator, seed, kind);
+      }
+    }
+  }
+
+  #[test]
+  fn room_levels_are_connected() {
+    assert_connected(MapGenerator::Rooms);
+  }
+
+  #[test]
+  fn bsp_levels_are_connected() {
+    assert_connected(MapGenerator::Bsp);
+  }
+
+  #[test]
+  fn cave_levels_are_connected() {
+    assert_connected(MapGenerator::Caves);
+  }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+
+use colors::Color;
+use game::MessageLog;
+
+// How many messages are kept unless the frontend asks for another limit.
+pub const DEFAULT_MESSAGE_LIMIT: usize = 500;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+  pub text: String,
+  pub color: Color,
+  // how many times in a row it was said
+  pub count: u32,
+}
+
+impl Message {
+  // The text as it's shown, with "x3" after it if it came three times running.
+  pub fn display(&self) -> String {
+    if self.count > 1 {
+      format!("{} x{}", self.text, self.count)
+    } else {
+      self.text.clone()
+    }
+  }
+}
+
+// The message log, oldest first. A message that's the same as the one before
+// it only bumps that one's count, and once there are more than `limit` the
+// oldest are forgotten.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Messages {
+  messages: VecDeque<Message>,
+  // not saved, so it's whatever the frontend running the game wants
+  #[serde(skip, default = "default_limit")]
+  limit: usize,
+  // every message ever added, repeats included, for telling when something new
+  // has been said
+  #[serde(skip)]
+  added: u64,
+}
+
+fn default_limit() -> usize {
+  DEFAULT_MESSAGE_LIMIT
+}
+
+impl Default for Messages {
+  fn default() -> Self {
+    Messages { messages: VecDeque::new(), limit: DEFAULT_MESSAGE_LIMIT, added: 0 }
+  }
+}
+
+impl Messages {
+  pub fn limit(&self) -> usize {
+    self.limit
+  }
+
+  // A limit of 0 is taken as 1; there's always room for the latest message.
+  pub fn set_limit(&mut self, limit: usize) {
+    self.limit = limit.max(1);
+    while self.messages.len() > self.limit {
+      self.messages.pop_front();
+    }
+  }
+
+  // How many messages have been added since the game was started or loaded.
+  pub fn added(&self) -> u64 {
+    self.added
+  }
+
+  pub fn len(&self) -> usize {
+    self.messages.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.messages.is_empty()
+  }
+
+  pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Message> + ExactSizeIterator {
+    self.messages.iter()
+  }
+}
+
+impl MessageLog for Messages {
+  fn add<T: Into<String>>(&mut self, message: T, color: Color) {
+    let text = message.into();
+    self.added += 1;
+    if let Some(last) = self.messages.back_mut() {
+      if last.text == text && last.color == color {
+        last.count += 1;
+        return;
+      }
+    }
+    self.messages.push_back(Message { text, color, count: 1 });
+    if self.messages.len() > self.limit {
+      self.messages.pop_front();
+    }
+  }
+}
@@ -0,0 +1,148 @@
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use game::{level_up_xp, total_xp, GameState};
+use item::stack_name;
+use map::{MAP_HEIGHT, MAP_WIDTH};
+use replay::GAME_VERSION;
+use save::{data_dir, format_timestamp, unix_time};
+
+// How many of the last messages go in a morgue file.
+const MORGUE_MESSAGES: usize = 20;
+
+pub fn morgue_dir() -> PathBuf {
+  data_dir().join("morgue")
+}
+
+// Writes the story of a finished run to a new file in the morgue directory
+// and returns where it went.
+pub fn write_morgue(state: &GameState, slot: &str) -> io::Result<PathBuf> {
+  let died_at = unix_time();
+  fs::create_dir_all(morgue_dir())?;
+  let path = morgue_dir().join(format!("{}-{}.txt", slot, died_at));
+  let mut file = File::create(&path)?;
+  file.write_all(morgue_text(state, slot, died_at).as_bytes())?;
+  Ok(path)
+}
+
+// A plain-text account of how the run ended: what killed the player and
+// where, their stats and belongings, the last few messages, and the level as
+// far as they'd explored it.
+pub fn morgue_text(state: &GameState, slot: &str, died_at: u64) -> String {
+  let player = state.player();
+  let game = &state.game;
+  let fighter = player.fighter;
+  let mut text = String::new();
+
+  let _ = writeln!(text, "The Glass Oak {} - {}", GAME_VERSION, slot);
+  let _ = writeln!(text, "Died {}, seed {}", format_timestamp(died_at), game.rng.seed());
+  let _ = writeln!(text);
+  let cause = game.cause_of_death.as_ref().map_or("something unknown", |cause| cause.as_str());
+  // "orc (orc-12)", but just "poison"
+  let cause = match game.killed_by {
+    Some(ref killer) if killer != cause => format!("{} ({})", cause, killer),
+    _ => cause.to_string(),
+  };
+  let _ = writeln!(text, "Killed by {} on dungeon level {}, turn {}.", cause, game.dungeon_level, game.turn);
+  let _ = writeln!(text);
+
+  let _ = writeln!(text, "Character level {}", player.level);
+  if let Some(fighter) = fighter {
+    let _ = writeln!(text, "Experience: {} (next level at {})", fighter.xp, level_up_xp(player.level));
+    let _ = writeln!(text, "HP: {}/{}", fighter.hp.max(0), player.max_hp(game));
+    let _ = writeln!(text, "Attack: {} ({} + {} from equipment)", player.power(game), fighter.power, player.power(game) - fighter.power);
+    let _ = writeln!(text, "Defense: {} ({} + {} from equipment)", player.defense(game), fighter.defense, player.defense(game) - fighter.defense);
+    let _ = writeln!(text, "Speed: {}", fighter.speed);
+  }
+  let _ = writeln!(text);
+
+  let stats = &game.stats;
+  let _ = writeln!(text, "Score: {}", stats.score(total_xp(player), game.turn));
+  let _ = writeln!(text, "Deepest level: {}", stats.deepest_level.max(game.dungeon_level));
+  let _ = writeln!(text, "Damage dealt: {}", stats.damage_dealt);
+  let _ = writeln!(text, "Damage taken: {}", stats.damage_taken);
+  let _ = writeln!(text);
+
+  let _ = writeln!(text, "Kills: {}", stats.total_kills());
+  for (monster, count) in &stats.kills {
+    let _ = writeln!(text, "  {} x{}", monster, count);
+  }
+  let _ = writeln!(text, "Items used:");
+  if stats.items_used.is_empty() {
+    let _ = writeln!(text, "  (none)");
+  }
+  for (item, count) in &stats.items_used {
+    let _ = writeln!(text, "  {} x{}", item, count);
+  }
+  let _ = writeln!(text, "Turns per level:");
+  for (level, turns) in stats.turns_on_levels(game.dungeon_level, game.turn) {
+    let _ = writeln!(text, "  level {}: {}", level, turns);
+  }
+  let _ = writeln!(text);
+
+  let _ = writeln!(text, "Inventory:");
+  if game.inventory.is_empty() {
+    let _ = writeln!(text, "  (nothing)");
+  }
+  for item in &game.inventory {
+    match item.equipment {
+      Some(equipment) if equipment.equipped => { let _ = writeln!(text, "  {} (on {})", stack_name(item), equipment.slot); }
+      _ => { let _ = writeln!(text, "  {}", stack_name(item)); }
+    }
+  }
+  let _ = writeln!(text);
+
+  let _ = writeln!(text, "Last messages:");
+  let skip = game.log.len().saturating_sub(MORGUE_MESSAGES);
+  for message in game.log.iter().skip(skip) {
+    let _ = writeln!(text, "  {}", message.display());
+  }
+  let _ = writeln!(text);
+
+  let _ = writeln!(text, "Dungeon level {}:", game.dungeon_level);
+  text.push_str(&map_dump(state));
+  text
+}
+
+// The explored part of the current level, walls as '#' and floor as '.', with
+// whatever the player could see or remember drawn on top.
+fn map_dump(state: &GameState) -> String {
+  let map = &state.game.map;
+  let mut rows: Vec<Vec<char>> = (0..MAP_HEIGHT).map(|y| {
+    (0..MAP_WIDTH).map(|x| {
+      let tile = &map[x as usize][y as usize];
+      match (tile.explored, tile.block_sight) {
+        (false, _) => ' ',
+        (true, true) => '#',
+        (true, false) => '.',
+      }
+    }).collect()
+  }).collect();
+
+  let mut objects: Vec<_> = state.objects.iter().filter(|object| {
+    state.fov.is_in_fov(object.x, object.y) ||
+      (object.always_visible && map[object.x as usize][object.y as usize].explored)
+  }).collect();
+  // blocking objects go on top, as on screen
+  objects.sort_by_key(|object| object.blocks);
+  for object in objects {
+    rows[object.y as usize][object.x as usize] = object.char;
+  }
+  let player = state.player();
+  rows[player.y as usize][player.x as usize] = player.char;
+
+  // only the rows that have something on them
+  let lines: Vec<String> = rows.into_iter().map(|row| {
+    row.into_iter().collect::<String>().trim_end().to_string()
+  }).collect();
+  let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(0);
+  let last = lines.iter().rposition(|line| !line.is_empty()).map_or(0, |last| last + 1);
+  let mut dump = String::new();
+  for line in lines.iter().take(last).skip(first) {
+    dump.push_str(line);
+    dump.push('\n');
+  }
+  dump
+}
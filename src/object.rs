@@ -0,0 +1,348 @@
+use std::cmp;
+use std::fmt;
+use rand::Rng;
+
+use ai::Ai;
+use colors::{self, Color};
+use fov::torch_radius;
+use game::{Event, Game, MessageLog, PLAYER};
+use item::Item;
+use map::{is_blocked, Map};
+use ranged::RangedAttack;
+use scheduler::{normal_speed, ATTACK_COST, MOVE_COST};
+use stats::monster_type;
+use status::{apply_status, OnHit, StatusEffect, StatusKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fighter {
+  pub max_hp: i32,
+  pub hp: i32,
+  pub defense: i32,
+  pub power: i32,
+  pub xp: i32,
+  pub on_death: DeathCallback,
+  #[serde(default)]
+  pub on_hit: Option<OnHit>,
+  #[serde(default = "normal_speed")]
+  pub speed: i32,
+  // how many tiles away it can see
+  #[serde(default = "torch_radius")]
+  pub vision: i32,
+  // below what percentage of its hp it runs away; 0 never does
+  #[serde(default)]
+  pub flee_below: i32,
+  #[serde(default)]
+  pub ranged: Option<RangedAttack>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeathCallback {
+  Player,
+  Monster,
+}
+
+impl DeathCallback {
+  fn callback(self, object: &mut Object, killer: Killer, game: &mut Game) {
+    use self::DeathCallback::*;
+    let callback: fn(&mut Object, Killer, &mut Game) = match self {
+      Player => player_death,
+      Monster => monster_death,
+    };
+    object.always_visible = true;
+    callback(object, killer, game);
+  }
+}
+
+// Who or what did some damage. Only the player earns anything for a kill.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Killer<'a> {
+  Player,
+  // a monster, by name
+  Monster(&'a str),
+  // anything else, as in "killed by <it>"
+  Environment(&'a str),
+}
+
+impl<'a> Killer<'a> {
+  pub fn name(self) -> &'a str {
+    match self {
+      Killer::Player => "player",
+      Killer::Monster(name) | Killer::Environment(name) => name,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Stairs {
+  Down,
+  Up,
+}
+
+// Something that can be worn or wielded. Items keep this while they sit in the
+// inventory, and only count towards the wearer's stats while `equipped`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Equipment {
+  pub slot: Slot,
+  #[serde(default)]
+  pub equipped: bool,
+  pub power_bonus: i32,
+  pub defense_bonus: i32,
+  pub max_hp_bonus: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Slot {
+  Weapon,
+  Shield,
+  Armor,
+  Helm,
+  Ring,
+}
+
+impl fmt::Display for Slot {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::Slot::*;
+    match *self {
+      Weapon => write!(f, "weapon"),
+      Shield => write!(f, "shield"),
+      Armor => write!(f, "armor"),
+      Helm => write!(f, "helm"),
+      Ring => write!(f, "ring"),
+    }
+  }
+}
+
+fn player_death(player: &mut Object, killer: Killer, game: &mut Game) {
+  game.log.add("You died!", colors::DARK_RED);
+  // "orc" for the high scores, and "orc-12" as well for the morgue file
+  game.cause_of_death = Some(monster_type(killer.name()).into());
+  game.killed_by = Some(killer.name().into());
+  game.events.push(Event::PlayerDied);
+  player.char = '@';
+  player.color = colors::DARK_RED;
+}
+
+fn monster_death(monster: &mut Object, killer: Killer, game: &mut Game) {
+  if killer == Killer::Player {
+    game.log.add(format!("{} is dead! You gain {} xp.", monster.name, monster.fighter.unwrap().xp), colors::DARK_RED);
+    game.stats.record_kill(&monster.name);
+  } else {
+    game.log.add(format!("{} is dead!", monster.name), colors::DARK_RED);
+  }
+  game.events.push(Event::Died { name: monster.name.clone() });
+  monster.char = '@';
+  monster.color = colors::DARK_RED;
+  monster.blocks = false;
+  monster.fighter = None;
+  monster.ai = None;
+  monster.name = format!("remains of {}", monster.name);
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Object {
+  pub x: i32,
+  pub y: i32,
+  pub char: char,
+  pub color: Color,
+  pub name: String,
+  pub blocks: bool,
+  pub alive: bool,
+  pub always_visible: bool,
+  pub level: i32,
+  pub fighter: Option<Fighter>,
+  pub ai: Option<Ai>,
+  pub item: Option<Item>,
+  #[serde(default)]
+  pub stairs: Option<Stairs>,
+  #[serde(default)]
+  pub equipment: Option<Equipment>,
+  #[serde(default)]
+  pub statuses: Vec<StatusEffect>,
+  // built up every tick and spent on actions; see `scheduler`
+  #[serde(default)]
+  pub energy: i32,
+  // how many there are of an item that stacks
+  #[serde(default = "single")]
+  pub count: u32,
+}
+
+fn single() -> u32 {
+  1
+}
+
+impl Object {
+  pub fn new (x: i32, y: i32, char: char, name: &str, color: Color, blocks: bool) -> Self {
+    Object {
+      x,
+      y,
+      char,
+      color,
+      name: name.into(),
+      blocks,
+      alive: false,
+      always_visible: false,
+      level: 1,
+      fighter: None,
+      ai: None,
+      item: None,
+      stairs: None,
+      equipment: None,
+      statuses: vec![],
+      energy: 0,
+      count: 1,
+    }
+  }
+
+  pub fn pos(&self) -> (i32, i32) {
+    (self.x, self.y)
+  }
+
+  pub fn set_pos(&mut self, x: i32, y: i32) {
+    self.x = x;
+    self.y = y;
+  }
+
+  pub fn distance_to(&self, other: &Object) -> f32 {
+    let dx = other.x - self.x;
+    let dy = other.y - self.y;
+    ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
+  }
+
+  pub fn distance(&self, x: i32, y: i32) -> f32 {
+    (((x - self.x).pow(2) + (y - self.y).pow(2)) as f32).sqrt()
+  }
+
+  pub fn has_status(&self, kind: StatusKind) -> bool {
+    self.statuses.iter().any(|status| status.kind == kind)
+  }
+
+  pub fn status_turns(&self, kind: StatusKind) -> Option<i32> {
+    self.statuses.iter().find(|status| status.kind == kind).map(|status| status.turns)
+  }
+
+  // Returns the xp the victim was worth if this killed it, for the caller to
+  // give to whoever earned it.
+  pub fn take_damage(&mut self, damage: i32, killer: Killer, game: &mut Game) -> Option<i32> {
+    if let Some(fighter) = self.fighter.as_mut() {
+      if damage > 0 {
+        fighter.hp -= damage;
+        if self.name == "player" {
+          game.stats.damage_taken += damage;
+        }
+      }
+    }
+    if let Some(fighter) = self.fighter {
+      if fighter.hp <= 0 {
+        self.alive = false;
+        fighter.on_death.callback(self, killer, game);
+        return Some(fighter.xp);
+      }
+    }
+    None
+  }
+
+  pub fn heal(&mut self, amount: i32, game: &Game) {
+    let max_hp = self.max_hp(game);
+    if let Some(ref mut fighter) = self.fighter {
+      fighter.hp += amount;
+      if fighter.hp > max_hp {
+        fighter.hp = max_hp;
+      }
+    }
+  }
+
+  // Only the player carries anything, so everyone else fights with their bare
+  // stats.
+  pub fn get_all_equipped(&self, game: &Game) -> Vec<Equipment> {
+    if self.name == "player" {
+      game.inventory.iter()
+        .filter_map(|item| item.equipment)
+        .filter(|equipment| equipment.equipped)
+        .collect()
+    } else {
+      vec![]
+    }
+  }
+
+  pub fn power(&self, game: &Game) -> i32 {
+    let base_power = self.fighter.map_or(0, |f| f.power);
+    let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.power_bonus).sum();
+    base_power + bonus
+  }
+
+  pub fn defense(&self, game: &Game) -> i32 {
+    let base_defense = self.fighter.map_or(0, |f| f.defense);
+    let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.defense_bonus).sum();
+    base_defense + bonus
+  }
+
+  pub fn max_hp(&self, game: &Game) -> i32 {
+    let base_max_hp = self.fighter.map_or(0, |f| f.max_hp);
+    let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.max_hp_bonus).sum();
+    base_max_hp + bonus
+  }
+
+  pub fn attack(&mut self, target: &mut Object, game: &mut Game) {
+    let damage = self.power(game) - target.defense(game);
+    game.events.push(Event::Attacked { attacker: self.name.clone(), target: target.name.clone(), damage: cmp::max(damage, 0) });
+    if damage > 0 {
+      game.log.add(format!("{} attacks {} for {} hit points.", self.name, target.name, damage), colors::YELLOW);
+      if self.name == "player" {
+        game.stats.damage_dealt += damage;
+      }
+      let killer = if self.name == "player" { Killer::Player } else { Killer::Monster(&self.name) };
+      if let Some(xp) = target.take_damage(damage, killer, game) {
+        self.fighter.as_mut().unwrap().xp += xp;
+      } else if let Some(on_hit) = self.fighter.and_then(|f| f.on_hit) {
+        if game.rng.gen_range(0, 100) < on_hit.chance {
+          apply_status(target, on_hit.status, on_hit.turns, game);
+        }
+      }
+    } else {
+      game.log.add(format!("{} attacks {} but it has no effect!", self.name, target.name),
+      colors::YELLOW);
+    }
+  }
+}
+
+pub fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
+  assert!(first_index != second_index);
+  let split_at_index = cmp::max(first_index, second_index);
+  let (first_slice, second_slice) = items.split_at_mut(split_at_index);
+  if first_index < second_index {
+    (&mut first_slice[first_index], &mut second_slice[0])
+  } else {
+    (&mut second_slice[0], &mut first_slice[second_index])
+  }
+}
+
+pub fn move_by (id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [Object]) {
+  let (x, y) = objects[id].pos();
+  if !is_blocked(x+dx, y+dy, map, objects) {
+    objects[id].set_pos(x + dx, y + dy);
+  }
+}
+
+// Moves the player, or attacks whatever is in the way. Returns the energy that
+// cost.
+pub fn player_move_or_attack(dx: i32, dy: i32, objects: &mut [Object], game: &mut Game) -> i32 {
+  let x = objects[PLAYER].x + dx;
+  let y = objects[PLAYER].y + dy;
+
+  let target_id = objects.iter().position(|object| {
+    object.fighter.is_some() && object.pos() == (x, y)
+  });
+
+  match target_id {
+    Some(target_id) => {
+      let (player, target) = mut_two(PLAYER, target_id, objects);
+      player.attack(target, game);
+      ATTACK_COST
+    },
+    None => {
+      move_by(PLAYER, dx, dy, &game.map, objects);
+      MOVE_COST
+    }
+  }
+}
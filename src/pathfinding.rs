@@ -0,0 +1,278 @@
+use std::cmp::{self, Reverse};
+use std::collections::BinaryHeap;
+
+use map::{in_bounds, Map, MAP_WIDTH, MAP_HEIGHT};
+use object::Object;
+
+// Costs are in tenths of a step so that diagonals can cost ~1.4 steps.
+const STRAIGHT_COST: i32 = 10;
+const DIAGONAL_COST: i32 = 14;
+// Another creature in the way is usually gone in a turn or two, so it's worth
+// waiting behind for a while before taking the long way round.
+const OCCUPIED_COST: i32 = 5 * STRAIGHT_COST;
+// How many tiles a monster may look at before giving up on a path, which keeps
+// a whole level's worth of monsters cheap on big maps.
+pub const MONSTER_SEARCH_LIMIT: usize = 1500;
+// How much more a safety map values getting away than the way there costs;
+// the higher, the more a fleeing monster will risk to reach somewhere far.
+const SAFETY_FACTOR_PERCENT: i32 = 120;
+
+pub const DIRECTIONS: [(i32, i32); 8] = [
+  (-1, -1), (0, -1), (1, -1),
+  (-1, 0), (1, 0),
+  (-1, 1), (0, 1), (1, 1),
+];
+
+fn index(x: i32, y: i32) -> usize {
+  (y * MAP_WIDTH + x) as usize
+}
+
+// Octile distance, which never overestimates with the costs above.
+fn heuristic((x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> i32 {
+  let dx = (x1 - x2).abs();
+  let dy = (y1 - y2).abs();
+  STRAIGHT_COST * cmp::max(dx, dy) + (DIAGONAL_COST - STRAIGHT_COST) * cmp::min(dx, dy)
+}
+
+// A* search from `from` to `to` over eight-way moves. `extra_cost` says what it
+// costs to step onto a tile on top of the move itself, or `None` if it can't be
+// entered at all. Gives up after expanding `limit` tiles. The returned path
+// leaves out `from` and ends with `to`.
+pub fn astar<F>(from: (i32, i32), to: (i32, i32), limit: usize, extra_cost: F) -> Option<Vec<(i32, i32)>>
+  where F: Fn(i32, i32) -> Option<i32>
+{
+  if !in_bounds(to.0, to.1) {
+    return None;
+  }
+  search(from, limit, |x, y| (x, y) == to, |x, y| heuristic((x, y), to), extra_cost)
+}
+
+// The cheapest path from `from` to whichever tile closest to it `is_goal`
+// picks out, other than `from` itself, with costs as for `astar`.
+pub fn path_to_nearest<F, G>(from: (i32, i32), is_goal: G, extra_cost: F) -> Option<Vec<(i32, i32)>>
+  where F: Fn(i32, i32) -> Option<i32>, G: Fn(i32, i32) -> bool
+{
+  let size = (MAP_WIDTH * MAP_HEIGHT) as usize;
+  search(from, size, |x, y| (x, y) != from && is_goal(x, y), |_, _| 0, extra_cost)
+}
+
+// Best-first search shared by `astar` and `path_to_nearest`: with a heuristic
+// it's A*, and with none it's Dijkstra's.
+fn search<F, G, H>(from: (i32, i32), limit: usize, is_goal: G, heuristic: H, extra_cost: F) -> Option<Vec<(i32, i32)>>
+  where F: Fn(i32, i32) -> Option<i32>, G: Fn(i32, i32) -> bool, H: Fn(i32, i32) -> i32
+{
+  if !in_bounds(from.0, from.1) {
+    return None;
+  }
+
+  let size = (MAP_WIDTH * MAP_HEIGHT) as usize;
+  let mut cost_so_far = vec![i32::MAX; size];
+  let mut came_from: Vec<Option<(i32, i32)>> = vec![None; size];
+  let mut open = BinaryHeap::new();
+  let mut expanded = 0;
+
+  cost_so_far[index(from.0, from.1)] = 0;
+  open.push(Reverse((heuristic(from.0, from.1), 0, from)));
+
+  while let Some(Reverse((_, cost, (x, y)))) = open.pop() {
+    if is_goal(x, y) {
+      let mut path = vec![(x, y)];
+      let mut current = (x, y);
+      while let Some(previous) = came_from[index(current.0, current.1)] {
+        if previous == from {
+          break;
+        }
+        path.push(previous);
+        current = previous;
+      }
+      path.reverse();
+      return Some(path);
+    }
+    if cost > cost_so_far[index(x, y)] {
+      continue;  // a cheaper way here was already expanded
+    }
+    expanded += 1;
+    if expanded > limit {
+      return None;
+    }
+
+    for &(dx, dy) in DIRECTIONS.iter() {
+      let (next_x, next_y) = (x + dx, y + dy);
+      if !in_bounds(next_x, next_y) {
+        continue;
+      }
+      let extra = match extra_cost(next_x, next_y) {
+        Some(extra) => extra,
+        None => continue,
+      };
+      let step = if dx != 0 && dy != 0 { DIAGONAL_COST } else { STRAIGHT_COST };
+      let next_cost = cost + step + extra;
+      let next = index(next_x, next_y);
+      if next_cost < cost_so_far[next] {
+        cost_so_far[next] = next_cost;
+        came_from[next] = Some((x, y));
+        let priority = next_cost + heuristic(next_x, next_y);
+        open.push(Reverse((priority, next_cost, (next_x, next_y))));
+      }
+    }
+  }
+  None
+}
+
+// The route a monster would walk to reach `to`: walls can't be crossed, and
+// tiles with something blocking on them are allowed but expensive.
+pub fn monster_path(from: (i32, i32), to: (i32, i32), map: &Map, objects: &[Object]) -> Option<Vec<(i32, i32)>> {
+  let mut occupied = vec![false; (MAP_WIDTH * MAP_HEIGHT) as usize];
+  for object in objects.iter().filter(|object| object.blocks && in_bounds(object.x, object.y)) {
+    occupied[index(object.x, object.y)] = true;
+  }
+
+  astar(from, to, MONSTER_SEARCH_LIMIT, |x, y| {
+    if map[x as usize][y as usize].blocked {
+      None
+    } else if occupied[index(x, y)] && (x, y) != to {
+      Some(OCCUPIED_COST)
+    } else {
+      Some(0)
+    }
+  })
+}
+
+// What it costs to get from each tile to the cheapest of `sources`, each of
+// which starts at its own value rather than at 0, walking only where
+// `passable` says.
+pub struct DijkstraMap {
+  values: Vec<i32>,
+}
+
+impl DijkstraMap {
+  pub fn new<F>(sources: &[((i32, i32), i32)], passable: F) -> Self
+    where F: Fn(i32, i32) -> bool
+  {
+    let size = (MAP_WIDTH * MAP_HEIGHT) as usize;
+    let mut values = vec![i32::MAX; size];
+    let mut open = BinaryHeap::new();
+    for &((x, y), value) in sources.iter().filter(|source| in_bounds((source.0).0, (source.0).1)) {
+      if value < values[index(x, y)] {
+        values[index(x, y)] = value;
+        open.push(Reverse((value, (x, y))));
+      }
+    }
+
+    while let Some(Reverse((value, (x, y)))) = open.pop() {
+      if value > values[index(x, y)] {
+        continue;
+      }
+      for &(dx, dy) in DIRECTIONS.iter() {
+        let (next_x, next_y) = (x + dx, y + dy);
+        if !in_bounds(next_x, next_y) || !passable(next_x, next_y) {
+          continue;
+        }
+        let step = if dx != 0 && dy != 0 { DIAGONAL_COST } else { STRAIGHT_COST };
+        let next = index(next_x, next_y);
+        if value + step < values[next] {
+          values[next] = value + step;
+          open.push(Reverse((value + step, (next_x, next_y))));
+        }
+      }
+    }
+    DijkstraMap { values }
+  }
+
+  // `None` where none of the sources can be reached from.
+  pub fn get(&self, x: i32, y: i32) -> Option<i32> {
+    if in_bounds(x, y) && self.values[index(x, y)] != i32::MAX {
+      Some(self.values[index(x, y)])
+    } else {
+      None
+    }
+  }
+}
+
+// Somewhere to run from `danger`: downhill on this map is away from it. It's
+// the distance from `danger` turned upside down and then smoothed out again,
+// so fleeing looks past the nearest far-off corner and would rather slip
+// past `danger` than be cornered in a dead end.
+pub fn safety_map(danger: (i32, i32), map: &Map) -> DijkstraMap {
+  let passable = |x: i32, y: i32| !map[x as usize][y as usize].blocked;
+  let distance = DijkstraMap::new(&[(danger, 0)], passable);
+  let mut sources = vec![];
+  for y in 0..MAP_HEIGHT {
+    for x in 0..MAP_WIDTH {
+      if let Some(value) = distance.get(x, y) {
+        sources.push(((x, y), -value * SAFETY_FACTOR_PERCENT / 100));
+      }
+    }
+  }
+  DijkstraMap::new(&sources, passable)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use colors;
+  use map::Tile;
+
+  fn open_map() -> Map {
+    vec![vec![Tile::empty(); MAP_HEIGHT as usize]; MAP_WIDTH as usize]
+  }
+
+  fn steps_are_single_moves(from: (i32, i32), path: &[(i32, i32)]) -> bool {
+    let mut previous = from;
+    path.iter().all(|&(x, y)| {
+      let single = (x - previous.0).abs() <= 1 && (y - previous.1).abs() <= 1;
+      previous = (x, y);
+      single
+    })
+  }
+
+  #[test]
+  fn paths_go_around_walls() {
+    let mut map = open_map();
+    for tile in map[10].iter_mut().take(16).skip(5) {
+      *tile = Tile::wall();
+    }
+    let path = monster_path((5, 10), (15, 10), &map, &[]).unwrap();
+    assert_eq!(path.last(), Some(&(15, 10)));
+    assert!(steps_are_single_moves((5, 10), &path));
+    assert!(path.iter().all(|&(x, y)| !map[x as usize][y as usize].blocked));
+    // straight through would have been 10 steps
+    assert!(path.len() > 10);
+  }
+
+  #[test]
+  fn occupied_tiles_cost_more_but_dont_block() {
+    let blocker = [Object::new(10, 10, 'o', "orc-1", colors::DESATURATED_GREEN, true)];
+
+    // in the open it's cheaper to step round
+    let map = open_map();
+    let path = monster_path((5, 10), (15, 10), &map, &blocker).unwrap();
+    assert!(!path.contains(&(10, 10)));
+
+    // down a corridor there's no way round, so it goes through
+    let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+    for column in map.iter_mut().take(16).skip(5) {
+      column[10] = Tile::empty();
+    }
+    let path = monster_path((5, 10), (15, 10), &map, &blocker).unwrap();
+    assert!(path.contains(&(10, 10)));
+    assert_eq!(path.len(), 10);
+  }
+
+  #[test]
+  fn searches_give_up_past_their_limit() {
+    let map = open_map();
+    let passable = |x: i32, y: i32| if map[x as usize][y as usize].blocked { None } else { Some(0) };
+    assert!(astar((1, 1), (60, 30), 10, passable).is_none());
+    assert!(astar((1, 1), (60, 30), (MAP_WIDTH * MAP_HEIGHT) as usize, passable).is_some());
+
+    // walling the goal in leaves monsters searching the whole map for a way,
+    // which is more than they're allowed to look at
+    let mut map = open_map();
+    for &(dx, dy) in DIRECTIONS.iter() {
+      map[(60 + dx) as usize][(30 + dy) as usize] = Tile::wall();
+    }
+    assert!((MAP_WIDTH * MAP_HEIGHT) as usize > MONSTER_SEARCH_LIMIT);
+    assert!(monster_path((1, 1), (60, 30), &map, &[]).is_none());
+  }
+}
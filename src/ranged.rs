@@ -0,0 +1,101 @@
+use std::cmp;
+use rand::Rng;
+
+use colors::{self, Color};
+use fov::{line, player_can_see};
+use game::{Event, Game, MessageLog, PLAYER};
+use object::{Killer, Object};
+
+// What a ranged monster shoots.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Projectile {
+  Arrow,
+  Bolt,
+}
+
+impl Projectile {
+  pub fn name(self) -> &'static str {
+    match self {
+      Projectile::Arrow => "arrow",
+      Projectile::Bolt => "magic bolt",
+    }
+  }
+
+  pub fn glyph(self) -> char {
+    match self {
+      Projectile::Arrow => '-',
+      Projectile::Bolt => '*',
+    }
+  }
+
+  pub fn color(self) -> Color {
+    match self {
+      Projectile::Arrow => colors::LIGHT_YELLOW,
+      Projectile::Bolt => colors::LIGHT_VIOLET,
+    }
+  }
+}
+
+// A monster's attack from afar: it shoots `projectile` up to `range` tiles,
+// hitting `accuracy` percent of the time for `power` damage less the target's
+// defense.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RangedAttack {
+  pub projectile: Projectile,
+  pub range: i32,
+  pub power: i32,
+  pub accuracy: u32,
+}
+
+// Shoots at `target` from where `shooter_id` stands. The shot flies straight
+// until it hits a wall, runs out of range, or hits something; anyone it passes
+// on the way gets a roll to be hit like the target does, and a miss flies on,
+// up to the target.
+pub fn fire(shooter_id: usize, target: (i32, i32), objects: &mut [Object], game: &mut Game) {
+  let ranged = match objects[shooter_id].fighter.and_then(|fighter| fighter.ranged) {
+    Some(ranged) => ranged,
+    None => return,
+  };
+  let from = objects[shooter_id].pos();
+  let shooter = objects[shooter_id].name.clone();
+  // monsters shooting at each other out of the player's sight go unreported
+  let player = objects[PLAYER].pos();
+  let shooter_seen = player_can_see(&game.map, player, from);
+  let seen = |victim: usize, objects: &[Object], game: &Game| {
+    victim == PLAYER || shooter_seen || player_can_see(&game.map, player, objects[victim].pos())
+  };
+  let mut path = vec![];
+  let mut hit = None;
+  for (x, y) in line(from, target).into_iter().skip(1).take(ranged.range as usize) {
+    if game.map[x as usize][y as usize].block_sight {
+      break;
+    }
+    path.push((x, y));
+    let victim = objects.iter().position(|object| object.pos() == (x, y) && object.alive && object.fighter.is_some());
+    if let Some(victim) = victim {
+      if game.rng.gen_range(0, 100) < ranged.accuracy {
+        hit = Some(victim);
+        break;
+      }
+      if seen(victim, objects, game) {
+        game.log.add(format!("{}'s {} misses {}.", shooter, ranged.projectile.name(), objects[victim].name), colors::LIGHT_GREY);
+      }
+    }
+  }
+  game.events.push(Event::ProjectileFired { shooter: shooter.clone(), path, projectile: ranged.projectile });
+
+  if let Some(victim) = hit {
+    let victim_seen = seen(victim, objects, game);
+    let victim = &mut objects[victim];
+    let damage = ranged.power - victim.defense(game);
+    game.events.push(Event::Attacked { attacker: shooter.clone(), target: victim.name.clone(), damage: cmp::max(damage, 0) });
+    if damage > 0 {
+      if victim_seen {
+        game.log.add(format!("{}'s {} hits {} for {} hit points.", shooter, ranged.projectile.name(), victim.name, damage), colors::YELLOW);
+      }
+      victim.take_damage(damage, Killer::Monster(&shooter), game);
+    } else if victim_seen {
+      game.log.add(format!("{}'s {} hits {} but it has no effect!", shooter, ranged.projectile.name(), victim.name), colors::YELLOW);
+    }
+  }
+}
@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use serde_json;
+
+use definitions::Definitions;
+use game::{new_game, Action, GameState};
+use save::{data_dir, unix_time};
+
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+const REPLAY_EXTENSION: &str = "replay";
+
+pub fn replay_dir() -> PathBuf {
+  data_dir().join("replays")
+}
+
+// Where the replay of a run goes: named after the save slot and when the run
+// started, so a new game in the slot of a dead one doesn't write over its
+// replay. They're kept after the save is gone, so a run can still be watched
+// once it's over.
+pub fn replay_path(slot: &str, started_at: u64) -> PathBuf {
+  replay_dir().join(format!("{}-{}.{}", slot, started_at, REPLAY_EXTENSION))
+}
+
+// A replay file, as the replay menu lists it.
+pub struct ReplayFile {
+  pub slot: String,
+  // seconds since the Unix epoch, or 0 if not known
+  pub started_at: u64,
+  pub path: PathBuf,
+}
+
+// Every replay in the replay directory, the most recently started first.
+pub fn list_replays() -> Vec<ReplayFile> {
+  let entries = match fs::read_dir(replay_dir()) {
+    Ok(entries) => entries,
+    Err(_) => return vec![],
+  };
+  let mut replays: Vec<ReplayFile> = entries
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().is_some_and(|extension| extension == REPLAY_EXTENSION))
+    .filter_map(|path| {
+      let stem = path.file_stem()?.to_str()?.to_string();
+      // slot names can have dashes in them too, so it's the last one
+      let (slot, started_at) = match stem.rfind('-').map(|dash| (&stem[..dash], stem[dash + 1..].parse())) {
+        Some((slot, Ok(started_at))) => (slot.to_string(), started_at),
+        _ => (stem.clone(), 0),
+      };
+      Some(ReplayFile { slot, started_at, path })
+    })
+    .collect();
+  replays.sort_by(|a, b| b.started_at.cmp(&a.started_at).then_with(|| a.slot.cmp(&b.slot)));
+  replays
+}
+
+// Everything needed to play a run back: the seed it started from and every
+// action the player took, in order. Keys and mouse clicks are recorded as the
+// `Action`s they turned into, since that is all `step` ever sees.
+//
+// On disk it's one JSON line with the version, seed and definitions, then
+// one line per action, so a file that's appended to as the game goes survives
+// a crash.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+  pub version: String,
+  pub seed: u64,
+  // the `fingerprint` of the definitions it was played with, which it only
+  // plays back the same with; None for replays from before it was recorded
+  #[serde(default)]
+  pub definitions: Option<u64>,
+  // seconds since the Unix epoch, or 0 if not known
+  #[serde(default)]
+  pub started_at: u64,
+  pub actions: Vec<Action>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+  version: String,
+  seed: u64,
+  #[serde(default)]
+  definitions: Option<u64>,
+  #[serde(default)]
+  started_at: u64,
+}
+
+impl Replay {
+  pub fn new(seed: u64, defs: &Definitions) -> Self {
+    Replay {
+      version: GAME_VERSION.into(),
+      seed,
+      definitions: Some(defs.fingerprint()),
+      started_at: unix_time(),
+      actions: vec![],
+    }
+  }
+
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let mut lines = BufReader::new(file).lines();
+    let header: Header = match lines.next() {
+      Some(line) => serde_json::from_str(&line?)?,
+      None => return Err(From::from("the replay file is empty")),
+    };
+    let mut replay = Replay {
+      version: header.version,
+      seed: header.seed,
+      definitions: header.definitions,
+      started_at: header.started_at,
+      actions: vec![],
+    };
+    // the header was line 1
+    for (number, line) in (2..).zip(lines) {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      let action: Action = serde_json::from_str(&line).map_err(|e| format!("line {}: {}", number, e))?;
+      if !action.is_valid() {
+        return Err(From::from(format!("line {}: {:?} is not an action the game could have taken", number, action)));
+      }
+      replay.actions.push(action);
+    }
+    Ok(replay)
+  }
+
+  pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let header = Header { version: self.version.clone(), seed: self.seed, definitions: self.definitions, started_at: self.started_at };
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", serde_json::to_string(&header)?)?;
+    self.write_actions(&mut file, 0)
+  }
+
+  // Adds the actions from `from` onwards to a file `write` already started.
+  pub fn append<P: AsRef<Path>>(&self, path: P, from: usize) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    self.write_actions(&mut file, from)
+  }
+
+  fn write_actions(&self, file: &mut File, from: usize) -> Result<(), Box<dyn Error>> {
+    for action in &self.actions[from..] {
+      writeln!(file, "{}", serde_json::to_string(action)?)?;
+    }
+    file.flush()?;
+    Ok(())
+  }
+
+  // A replay only plays back the same with the definitions it was recorded
+  // with, so it's refused with any others.
+  pub fn check_definitions(&self, defs: &Definitions) -> Result<(), Box<dyn Error>> {
+    match self.definitions {
+      Some(fingerprint) if fingerprint != defs.fingerprint() => {
+        Err(From::from("the replay was recorded with different monster and item definitions"))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  pub fn simulate(&self, defs: &Definitions) -> Result<GameState, Box<dyn Error>> {
+    self.check_definitions(defs)?;
+    let mut state = new_game(self.seed, defs.clone());
+    for action in &self.actions {
+      state.step(*action);
+    }
+    Ok(state)
+  }
+}
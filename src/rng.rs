@@ -0,0 +1,46 @@
+use rand::{self, Rng};
+
+// The one random number generator a run uses. It's a SplitMix64 generator
+// rather than one of rand's, because its state has to go into the save file
+// and rand's generators can't be serialized.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameRng {
+  seed: u64,
+  state: u64,
+}
+
+impl GameRng {
+  pub fn new(seed: u64) -> Self {
+    GameRng { seed, state: seed }
+  }
+
+  // A fresh seed for runs that weren't given one.
+  pub fn random_seed() -> u64 {
+    rand::thread_rng().gen()
+  }
+
+  pub fn seed(&self) -> u64 {
+    self.seed
+  }
+}
+
+// Saves from before the generator was stored get a fresh, random one.
+impl Default for GameRng {
+  fn default() -> Self {
+    GameRng::new(GameRng::random_seed())
+  }
+}
+
+impl Rng for GameRng {
+  fn next_u32(&mut self) -> u32 {
+    (self.next_u64() >> 32) as u32
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+  }
+}
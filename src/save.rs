@@ -0,0 +1,507 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::{self, Map, Value};
+
+use definitions::Definitions;
+use game::{Game, GameState, PLAYER};
+use object::Object;
+use replay::GAME_VERSION;
+
+// Where saves used to go before there were slots; one left there is moved into
+// the save directory by `import_old_save`.
+pub const OLD_SAVE_FILE: &str = "savegame";
+const SAVE_EXTENSION: &str = "sav";
+// Bumped whenever a change to the saved structs needs old saves rewritten; each
+// bump comes with a step in `MIGRATIONS` to bring the previous version forward.
+// Version 1 is the headerless `[objects, game]` pair the game used to write,
+// and version 2 the same with a header, all on one line.
+pub const SAVE_VERSION: u32 = 4;
+
+type Migration = fn(Value) -> Result<Value, String>;
+
+// MIGRATIONS[n] turns a version n + 1 save into a version n + 2 one.
+const MIGRATIONS: &[Migration] = &[
+  migrate_v1,
+  migrate_v2,
+  migrate_v3,
+];
+
+// What the load menu shows about a slot, kept in the save's header so the
+// menu doesn't have to read whole games to list them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SlotInfo {
+  pub character_level: i32,
+  pub dungeon_level: u32,
+  pub turn: u64,
+  // seconds since the Unix epoch, or 0 if not known
+  pub saved_at: u64,
+}
+
+pub struct Slot {
+  pub name: String,
+  // None if the save can't be read
+  pub info: Option<SlotInfo>,
+}
+
+// On disk a save is a header line, with the format and game versions and the
+// slot's `SlotInfo`, followed by a line holding the game itself.
+#[derive(Serialize, Deserialize)]
+struct Header {
+  format_version: u32,
+  game_version: String,
+  info: SlotInfo,
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+  objects: &'a [Object],
+  game: &'a Game,
+}
+
+// the header has already been checked by the time this is read
+#[derive(Deserialize)]
+struct SaveFile {
+  objects: Vec<Object>,
+  game: Game,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+  // there's no save to load
+  Missing,
+  // the file is there but isn't a save this game can make sense of
+  Corrupt(String),
+  // written by a later version of the game, in a format this one doesn't know
+  TooNew { format_version: u32, game_version: String },
+  Io(io::Error),
+}
+
+impl fmt::Display for SaveError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      SaveError::Missing => write!(f, "There is no saved game."),
+      SaveError::Corrupt(ref reason) => write!(f, "The saved game is damaged and can't be loaded ({}).", reason),
+      SaveError::TooNew { ref game_version, .. } =>
+        write!(f, "The saved game is from a newer version of the game ({}, this is {}).", game_version, GAME_VERSION),
+      SaveError::Io(ref e) => write!(f, "The saved game couldn't be read or written: {}", e),
+    }
+  }
+}
+
+impl Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+  fn from(e: io::Error) -> Self {
+    SaveError::Io(e)
+  }
+}
+
+// The game's folder in the XDG data directory (~/.local/share unless
+// XDG_DATA_HOME says otherwise), or under %APPDATA% on Windows. If none of
+// those are set it's the current directory.
+pub fn data_dir() -> PathBuf {
+  let data_dir = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").filter(|dir| !dir.is_empty()).map(|home| Path::new(&home).join(".local").join("share")))
+    .or_else(|| env::var_os("APPDATA").filter(|dir| !dir.is_empty()).map(PathBuf::from));
+  match data_dir {
+    Some(dir) => dir.join("the_glass_oak"),
+    None => PathBuf::from("."),
+  }
+}
+
+pub fn save_dir() -> PathBuf {
+  data_dir().join("saves")
+}
+
+pub fn unix_time() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+// "2026-10-18 14:02 UTC", from seconds since the Unix epoch.
+pub fn format_timestamp(seconds: u64) -> String {
+  let days = (seconds / 86_400) as i64;
+  let minutes_today = (seconds % 86_400) / 60;
+  // days to a civil date, from Howard Hinnant's `civil_from_days`
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let day_of_era = z - era * 146_097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, minutes_today / 60, minutes_today % 60)
+}
+
+// Slot names become file names, so they're kept to letters, digits, spaces,
+// dashes and underscores.
+pub fn valid_slot_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_'
+}
+
+pub fn valid_slot_name(name: &str) -> bool {
+  !name.trim().is_empty() && name.chars().all(valid_slot_char)
+}
+
+fn slot_path(name: &str) -> PathBuf {
+  save_dir().join(format!("{}.{}", name, SAVE_EXTENSION))
+}
+
+pub fn slot_exists(name: &str) -> bool {
+  slot_path(name).exists()
+}
+
+// Every save in the save directory, most recently saved first.
+pub fn list_slots() -> Vec<Slot> {
+  let entries = match fs::read_dir(save_dir()) {
+    Ok(entries) => entries,
+    Err(_) => return vec![],
+  };
+  let mut slots: Vec<Slot> = entries
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().is_some_and(|extension| extension == SAVE_EXTENSION))
+    .filter_map(|path| {
+      let name = path.file_stem()?.to_str()?.to_string();
+      Some(Slot { name, info: read_info(&path).ok() })
+    })
+    .collect();
+  slots.sort_by(|a, b| {
+    let saved_at = |slot: &Slot| slot.info.as_ref().map_or(0, |info| info.saved_at);
+    saved_at(b).cmp(&saved_at(a)).then_with(|| a.name.cmp(&b.name))
+  });
+  slots
+}
+
+// Writes the save next to the real one first and then moves it into place, so
+// a crash half way through leaves the last good save behind.
+pub fn save_game(slot: &str, objects: &[Object], game: &Game) -> Result<(), SaveError> {
+  let saved_at = unix_time();
+  let header = Header {
+    format_version: SAVE_VERSION,
+    game_version: GAME_VERSION.into(),
+    info: SlotInfo {
+      character_level: objects[PLAYER].level,
+      dungeon_level: game.dungeon_level,
+      turn: game.turn,
+      saved_at,
+    },
+  };
+  let body = Body { objects, game };
+
+  let path = slot_path(slot);
+  let temp_path = path.with_extension(format!("{}.tmp", SAVE_EXTENSION));
+  fs::create_dir_all(save_dir())?;
+  {
+    let mut file = File::create(&temp_path)?;
+    writeln!(file, "{}", serde_json::to_string(&header).map_err(io::Error::from)?)?;
+    writeln!(file, "{}", serde_json::to_string(&body).map_err(io::Error::from)?)?;
+    file.sync_all()?;
+  }
+  fs::rename(&temp_path, &path)?;
+  Ok(())
+}
+
+pub fn load_game(slot: &str, defs: Definitions) -> Result<GameState, SaveError> {
+  let save = read_save(&slot_path(slot))?;
+  let save: SaveFile = serde_json::from_value(save).map_err(|e| SaveError::Corrupt(e.to_string()))?;
+  let mut game = save.game;
+  game.defs = defs;
+  Ok(GameState::new(save.objects, game))
+}
+
+pub fn delete_slot(slot: &str) -> Result<(), SaveError> {
+  match fs::remove_file(slot_path(slot)) {
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(SaveError::Missing),
+    result => result.map_err(SaveError::Io),
+  }
+}
+
+// Moves a save from before there were slots into the save directory, as a slot
+// called "savegame", so it shows up in the load menu.
+pub fn import_old_save() -> Result<(), SaveError> {
+  let old_save = Path::new(OLD_SAVE_FILE);
+  if !old_save.exists() || slot_exists(OLD_SAVE_FILE) {
+    return Ok(());
+  }
+  fs::create_dir_all(save_dir())?;
+  fs::copy(old_save, slot_path(OLD_SAVE_FILE))?;
+  fs::remove_file(old_save)?;
+  Ok(())
+}
+
+fn read_info(path: &Path) -> Result<SlotInfo, SaveError> {
+  let mut first_line = String::new();
+  BufReader::new(open(path)?).read_line(&mut first_line)?;
+  if let Ok(header) = serde_json::from_str::<Header>(&first_line) {
+    if header.format_version > SAVE_VERSION {
+      return Err(SaveError::TooNew { format_version: header.format_version, game_version: header.game_version });
+    }
+    return Ok(header.info);
+  }
+  // older saves keep everything on the one line, so it takes the whole thing
+  let save = read_save(path)?;
+  serde_json::from_value(save["info"].clone()).map_err(|e| SaveError::Corrupt(e.to_string()))
+}
+
+fn open(path: &Path) -> Result<File, SaveError> {
+  File::open(path).map_err(|e| match e.kind() {
+    io::ErrorKind::NotFound => SaveError::Missing,
+    _ => SaveError::Io(e),
+  })
+}
+
+// Reads a save of any version there's ever been and brings it up to date, as
+// a single JSON object with the header's fields alongside `objects` and `game`.
+fn read_save(path: &Path) -> Result<Value, SaveError> {
+  let mut json_save_state = String::new();
+  open(path)?.read_to_string(&mut json_save_state)?;
+  parse_save(&json_save_state)
+}
+
+fn parse_save(json_save_state: &str) -> Result<Value, SaveError> {
+  let mut lines = json_save_state.splitn(2, '\n');
+  let first: Value = serde_json::from_str(lines.next().unwrap_or(""))
+    .map_err(|e| SaveError::Corrupt(e.to_string()))?;
+  let mut save = if first.get("info").is_some() {
+    let body: Value = serde_json::from_str(lines.next().unwrap_or(""))
+      .map_err(|e| SaveError::Corrupt(e.to_string()))?;
+    merge(first, body)?
+  } else {
+    first
+  };
+
+  let version = save_version(&save)?;
+  if version > SAVE_VERSION {
+    let game_version = save.get("game_version").and_then(|v| v.as_str()).unwrap_or("unknown").into();
+    return Err(SaveError::TooNew { format_version: version, game_version });
+  }
+  for migration in &MIGRATIONS[(version - 1) as usize..] {
+    save = migration(save).map_err(SaveError::Corrupt)?;
+  }
+  Ok(save)
+}
+
+fn merge(header: Value, body: Value) -> Result<Value, SaveError> {
+  match (header, body) {
+    (Value::Object(mut header), Value::Object(body)) => {
+      header.extend(body);
+      Ok(Value::Object(header))
+    }
+    _ => Err(SaveError::Corrupt("expected a header and a game".into())),
+  }
+}
+
+fn save_version(save: &Value) -> Result<u32, SaveError> {
+  if save.is_array() {
+    return Ok(1);
+  }
+  match save.get("format_version").and_then(|v| v.as_u64()) {
+    Some(version) if version >= 1 => Ok(version as u32),
+    _ => Err(SaveError::Corrupt("it has no format version".into())),
+  }
+}
+
+// Runs `fix` on every object in a save: those on the current level, in the
+// inventory, and on each level the player has left.
+fn for_each_object<F>(save: &mut Value, mut fix: F) where F: FnMut(&mut Map<String, Value>) {
+  fn fix_list<F: FnMut(&mut Map<String, Value>)>(list: Option<&mut Value>, fix: &mut F) {
+    let objects = list.and_then(|list| list.as_array_mut()).into_iter().flat_map(|list| list.iter_mut());
+    for object in objects.filter_map(|object| object.as_object_mut()) {
+      fix(object);
+    }
+  }
+
+  fix_list(save.get_mut("objects"), &mut fix);
+  if let Some(game) = save.get_mut("game") {
+    fix_list(game.get_mut("inventory"), &mut fix);
+    if let Some(levels) = game.get_mut("levels").and_then(|levels| levels.as_object_mut()) {
+      for level in levels.values_mut() {
+        fix_list(level.get_mut("objects"), &mut fix);
+      }
+    }
+  }
+}
+
+// Version 1 saves had no header, a single unmarked staircase per level, and
+// confusion stored as an AI that wrapped the monster's real one.
+fn migrate_v1(save: Value) -> Result<Value, String> {
+  let (objects, game) = match save {
+    Value::Array(mut pair) => {
+      if pair.len() != 2 {
+        return Err("expected objects and game state".into());
+      }
+      let game = pair.pop().unwrap();
+      (pair.pop().unwrap(), game)
+    }
+    _ => return Err("expected objects and game state".into()),
+  };
+  let mut save = json!({
+    "format_version": 2,
+    "game_version": "unknown",
+    "objects": objects,
+    "game": game,
+  });
+
+  for_each_object(&mut save, |object| {
+    let unmarked_stairs = object.get("name").and_then(|name| name.as_str()) == Some("stairs") &&
+      object.get("stairs").is_none_or(|stairs| stairs.is_null());
+    if unmarked_stairs {
+      object.insert("name".into(), json!("down stairs"));
+      object.insert("char".into(), json!(">"));
+      object.insert("stairs".into(), json!("Down"));
+    }
+
+    let mut confused_turns = None;
+    while let Some(confused) = object.get("ai").and_then(|ai| ai.get("Confused")).cloned() {
+      let turns = confused.get("num_turns").and_then(|turns| turns.as_i64()).unwrap_or(0);
+      confused_turns = Some(confused_turns.unwrap_or(0).max(turns));
+      object.insert("ai".into(), confused.get("previous_ai").cloned().unwrap_or(json!("Basic")));
+    }
+    if let Some(turns) = confused_turns {
+      let status = json!({"kind": "Confused", "turns": turns.max(1)});
+      let statuses = object.entry("statuses").or_insert_with(|| json!([]));
+      if let Some(statuses) = statuses.as_array_mut() {
+        statuses.push(status);
+      }
+    }
+  });
+  Ok(save)
+}
+
+// Version 2 saves had no slot info in the header, so it's rebuilt from the
+// game itself. When they were saved isn't known.
+fn migrate_v2(mut save: Value) -> Result<Value, String> {
+  let character_level = save["objects"][PLAYER]["level"].as_i64().unwrap_or(1);
+  let dungeon_level = save["game"]["dungeon_level"].as_u64().unwrap_or(1);
+  let turn = save["game"]["turn"].as_u64().unwrap_or(0);
+  match save.as_object_mut() {
+    Some(save) => {
+      save.insert("format_version".into(), json!(3));
+      save.insert("info".into(), json!({
+        "character_level": character_level,
+        "dungeon_level": dungeon_level,
+        "turn": turn,
+        "saved_at": 0,
+      }));
+    }
+    None => return Err("expected a header and a game".into()),
+  }
+  Ok(save)
+}
+
+// Version 3 saves kept the message log as a plain list of `[text, color]`
+// pairs, with nothing collapsed and no limit.
+fn migrate_v3(mut save: Value) -> Result<Value, String> {
+  let old_log = match save["game"]["log"].take() {
+    Value::Array(log) => log,
+    _ => return Err("expected a message log".into()),
+  };
+  let mut messages: Vec<Value> = vec![];
+  for message in old_log {
+    let (text, color) = match message {
+      Value::Array(ref pair) if pair.len() == 2 => (pair[0].clone(), pair[1].clone()),
+      _ => return Err("expected a message and its color".into()),
+    };
+    if let Some(last) = messages.last_mut() {
+      if last["text"] == text && last["color"] == color {
+        let count = last["count"].as_u64().unwrap_or(1);
+        last["count"] = json!(count + 1);
+        continue;
+      }
+    }
+    messages.push(json!({"text": text, "color": color, "count": 1}));
+  }
+  save["game"]["log"] = json!({"messages": messages});
+  save["format_version"] = json!(4);
+  Ok(save)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ai::Ai;
+  use object::Stairs;
+  use status::StatusKind;
+
+  // Saves as each old version of the game wrote them, cut down to a tiny map.
+  const V1: &str = include_str!("../tests/fixtures/save_v1.sav");
+  const V2: &str = include_str!("../tests/fixtures/save_v2.sav");
+  const V3: &str = include_str!("../tests/fixtures/save_v3.sav");
+
+  // Brings the save up to date and checks it loads as a current one.
+  fn load(json: &str) -> (Value, SaveFile) {
+    let save = parse_save(json).unwrap();
+    assert_eq!(save["format_version"], json!(SAVE_VERSION));
+    let file = serde_json::from_value(save.clone()).unwrap();
+    (save, file)
+  }
+
+  #[test]
+  fn version_1_saves_load() {
+    let (save, file) = load(V1);
+    assert_eq!(save_version(&serde_json::from_str(V1.trim()).unwrap()).unwrap(), 1);
+    // version 1 never said which game wrote it
+    assert_eq!(save["game_version"], json!("unknown"));
+    assert_eq!(file.game.dungeon_level, 2);
+    assert_eq!(file.game.inventory.len(), 1);
+
+    let orc = &file.objects[1];
+    assert_eq!(orc.ai, Some(Ai::Basic));
+    assert_eq!(orc.status_turns(StatusKind::Confused), Some(5));
+
+    let stairs = &file.objects[2];
+    assert_eq!(stairs.name, "down stairs");
+    assert_eq!(stairs.char, '>');
+    assert_eq!(stairs.stairs, Some(Stairs::Down));
+  }
+
+  #[test]
+  fn version_2_saves_get_slot_info() {
+    let (save, _) = load(V2);
+    let info: SlotInfo = serde_json::from_value(save["info"].clone()).unwrap();
+    assert_eq!((info.character_level, info.dungeon_level, info.turn, info.saved_at), (3, 3, 57, 0));
+  }
+
+  #[test]
+  fn version_3_saves_collapse_repeated_messages() {
+    let (save, file) = load(V3);
+    assert_eq!(save["info"]["saved_at"], json!(1_760_000_000));
+    let messages = &save["game"]["log"]["messages"];
+    assert_eq!(messages.as_array().unwrap().len(), 2);
+    assert_eq!(messages[0]["text"], json!("The orc attacks!"));
+    assert_eq!(messages[0]["count"], json!(2));
+    assert_eq!(file.game.turn, 120);
+  }
+
+  #[test]
+  fn saves_from_later_versions_are_refused() {
+    let header = json!({
+      "format_version": SAVE_VERSION + 1,
+      "game_version": "9.9.9",
+      "info": {"character_level": 1, "dungeon_level": 1, "turn": 0, "saved_at": 0},
+    });
+    match parse_save(&format!("{}\n{{}}\n", header)) {
+      Err(SaveError::TooNew { format_version, ref game_version }) => {
+        assert_eq!(format_version, SAVE_VERSION + 1);
+        assert_eq!(game_version, "9.9.9");
+      }
+      other => panic!("expected TooNew, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn truncated_saves_are_corrupt() {
+    for save in &[V1, V2, V3] {
+      match parse_save(&save[..save.len() / 2]) {
+        Err(SaveError::Corrupt(_)) => {}
+        other => panic!("expected Corrupt, got {:?}", other),
+      }
+    }
+  }
+}
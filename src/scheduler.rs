@@ -0,0 +1,65 @@
+use ai::ai_take_turn;
+use game::{Game, PLAYER};
+use object::Object;
+use status::{tick_statuses, StatusKind};
+
+// Everything that fights builds up energy at its speed every tick of game
+// time, and gets to act whenever it has enough to pay for an action. At
+// `NORMAL_SPEED` that's one action per tick; a bat at twice that speed gets
+// two, and a zombie at half of it gets one every other tick.
+pub const NORMAL_SPEED: i32 = 100;
+// the energy it takes to be allowed to act at all
+pub const FULL_TURN: i32 = 100;
+// What the player's actions cost. Moving, attacking and waiting take a whole
+// turn, and so does using an item, which used to be free and let a player
+// drink any number of potions in the middle of a fight. Picking things up and
+// dropping them are quick, so two of them go in a turn. Levelling up and
+// taking the stairs are free, the stairs since a level is entered without
+// anything around to act yet.
+pub const MOVE_COST: i32 = FULL_TURN;
+pub const ATTACK_COST: i32 = FULL_TURN;
+pub const WAIT_COST: i32 = FULL_TURN;
+pub const USE_ITEM_COST: i32 = FULL_TURN;
+pub const PICK_UP_COST: i32 = FULL_TURN / 2;
+pub const DROP_COST: i32 = FULL_TURN / 2;
+pub const FREE_ACTION_COST: i32 = 0;
+// a monster's turn costs the same whatever it did with it
+pub const MONSTER_TURN_COST: i32 = FULL_TURN;
+
+// the default for a serialized `speed`
+pub fn normal_speed() -> i32 {
+  NORMAL_SPEED
+}
+
+// How fast an object is right now, once its statuses are taken into account.
+pub fn effective_speed(object: &Object) -> i32 {
+  let speed = object.fighter.map_or(0, |f| f.speed);
+  if object.has_status(StatusKind::Paralyzed) {
+    return 0;
+  }
+  match (object.has_status(StatusKind::Hasted), object.has_status(StatusKind::Slowed)) {
+    (true, false) => speed * 2,
+    (false, true) => speed / 2,
+    _ => speed,
+  }
+}
+
+// Lets game time run after the player has acted, tick by tick, with monsters
+// acting as their energy allows, until the player can act again.
+pub fn run_until_player_ready(objects: &mut [Object], game: &mut Game) {
+  while objects[PLAYER].alive && objects[PLAYER].energy < FULL_TURN {
+    game.turn += 1;
+    tick_statuses(objects, game);
+    for object in objects.iter_mut().filter(|object| object.alive) {
+      object.energy += effective_speed(object);
+    }
+
+    for id in 0..objects.len() {
+      while objects[PLAYER].alive && objects[id].alive && objects[id].ai.is_some() &&
+        objects[id].energy >= FULL_TURN {
+        ai_take_turn(id, objects, game);
+        objects[id].energy -= MONSTER_TURN_COST;
+      }
+    }
+  }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+// What goes into a run's score: every level below the first is worth
+// `DEPTH_POINTS`, every xp point one, and every kill `KILL_POINTS` more. Every
+// `TURNS_PER_POINT` turns taken cost a point back, up to half the total, so a
+// quicker run scores higher but a long one is never worth less than a short
+// one that got nowhere.
+pub const DEPTH_POINTS: u64 = 500;
+pub const KILL_POINTS: u64 = 10;
+pub const TURNS_PER_POINT: u64 = 10;
+
+// A running tally of what happened over a run, kept with the game so it
+// survives saving and loading.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunStats {
+  // by monster type, e.g. "orc"
+  pub kills: BTreeMap<String, u32>,
+  pub damage_dealt: i32,
+  pub damage_taken: i32,
+  // by item name, counting only items that were used up
+  pub items_used: BTreeMap<String, u32>,
+  // turns spent on each dungeon level, not counting the one the player is on
+  pub turns_per_level: BTreeMap<u32, u64>,
+  // the turn the player arrived on the current level
+  pub level_started: u64,
+  pub deepest_level: u32,
+}
+
+impl RunStats {
+  pub fn record_kill(&mut self, name: &str) {
+    *self.kills.entry(monster_type(name).into()).or_insert(0) += 1;
+  }
+
+  pub fn record_item_used(&mut self, name: &str) {
+    *self.items_used.entry(name.into()).or_insert(0) += 1;
+  }
+
+  // Called when the player leaves `level` on `turn` for `to`.
+  pub fn record_level_change(&mut self, level: u32, to: u32, turn: u64) {
+    *self.turns_per_level.entry(level).or_insert(0) += turn - self.level_started;
+    self.level_started = turn;
+    self.deepest_level = self.deepest_level.max(to);
+  }
+
+  pub fn total_kills(&self) -> u32 {
+    self.kills.values().sum()
+  }
+
+  // The turns spent on each level so far, including the current one.
+  pub fn turns_on_levels(&self, current_level: u32, turn: u64) -> BTreeMap<u32, u64> {
+    let mut turns = self.turns_per_level.clone();
+    *turns.entry(current_level).or_insert(0) += turn - self.level_started;
+    turns
+  }
+
+  // `xp` is all the player has ever earned; see `game::total_xp`.
+  pub fn score(&self, xp: i32, turn: u64) -> u64 {
+    let depth = u64::from(self.deepest_level.saturating_sub(1));
+    let points = depth * DEPTH_POINTS + xp.max(0) as u64 + u64::from(self.total_kills()) * KILL_POINTS;
+    points - (turn / TURNS_PER_POINT).min(points / 2)
+  }
+}
+
+// Monsters are named after their type with a number to tell them apart, as
+// in "orc-12"; this is the "orc".
+pub fn monster_type(name: &str) -> &str {
+  match name.rfind('-') {
+    Some(dash) if !name[dash + 1..].is_empty() && name[dash + 1..].chars().all(|c| c.is_ascii_digit()) => &name[..dash],
+    _ => name,
+  }
+}
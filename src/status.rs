@@ -0,0 +1,212 @@
+use colors::{self, Color};
+use game::{Event, Game, MessageLog, PLAYER};
+use object::{Killer, Object};
+
+pub const POISON_DAMAGE: i32 = 2;
+pub const BURN_DAMAGE: i32 = 3;
+pub const REGENERATION_AMOUNT: i32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StatusKind {
+  Confused,
+  Poisoned,
+  Burning,
+  Slowed,
+  Hasted,
+  Regenerating,
+  Paralyzed,
+}
+
+impl StatusKind {
+  pub fn name(self) -> &'static str {
+    use self::StatusKind::*;
+    match self {
+      Confused => "confused",
+      Poisoned => "poisoned",
+      Burning => "burning",
+      Slowed => "slowed",
+      Hasted => "hasted",
+      Regenerating => "regenerating",
+      Paralyzed => "paralyzed",
+    }
+  }
+
+  pub fn color(self) -> Color {
+    use self::StatusKind::*;
+    match self {
+      Confused => colors::LIGHT_GREEN,
+      Poisoned => colors::GREEN,
+      Burning => colors::ORANGE,
+      Slowed => colors::LIGHT_BLUE,
+      Hasted => colors::LIGHT_YELLOW,
+      Regenerating => colors::LIGHT_VIOLET,
+      Paralyzed => colors::LIGHT_GREY,
+    }
+  }
+}
+
+// A condition that wears off after `turns` more turns. An object can have any
+// number of these at once, but only one of each kind.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffect {
+  pub kind: StatusKind,
+  pub turns: i32,
+}
+
+// What a monster's hits can do on top of the damage: `chance` percent of hits
+// that get through leave the target with `status` for `turns` turns.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OnHit {
+  pub status: StatusKind,
+  pub turns: i32,
+  pub chance: u32,
+}
+
+// Gives the object the status for `turns` turns. Getting one it already has
+// only ever makes it last longer.
+pub fn apply_status(object: &mut Object, kind: StatusKind, turns: i32, game: &mut Game) {
+  if !object.alive || turns <= 0 {
+    return;
+  }
+  if let Some(status) = object.statuses.iter_mut().find(|status| status.kind == kind) {
+    status.turns = status.turns.max(turns);
+    return;
+  }
+  object.statuses.push(StatusEffect { kind, turns });
+
+  let message = match (object.name == "player", kind) {
+    (true, _) => format!("You are {}!", kind.name()),
+    (false, StatusKind::Confused) => format!("The eyes of {} look vacant, as he starts to stumble around!", object.name),
+    (false, _) => format!("The {} is {}!", object.name, kind.name()),
+  };
+  game.log.add(message, kind.color());
+  game.events.push(Event::StatusApplied { name: object.name.clone(), status: kind });
+}
+
+// Runs every object's statuses for one turn: poison and fire hurt, regeneration
+// heals, and anything that has run its course wears off.
+pub fn tick_statuses(objects: &mut [Object], game: &mut Game) {
+  for (id, object) in objects.iter_mut().enumerate() {
+    if object.statuses.is_empty() {
+      continue;
+    }
+
+    let statuses = object.statuses.clone();
+    for status in &statuses {
+      if !object.alive {
+        break;  // the dead don't burn or heal
+      }
+      // poison and fire kill for nobody, so their xp goes unclaimed
+      match status.kind {
+        StatusKind::Poisoned => {
+          let message = if id == PLAYER {
+            format!("You take {} hit points of poison damage.", POISON_DAMAGE)
+          } else {
+            format!("The {} takes {} hit points of poison damage.", object.name, POISON_DAMAGE)
+          };
+          game.log.add(message, colors::GREEN);
+          object.take_damage(POISON_DAMAGE, Killer::Environment("poison"), game);
+        }
+        StatusKind::Burning => {
+          let message = if id == PLAYER {
+            format!("You burn for {} hit points.", BURN_DAMAGE)
+          } else {
+            format!("The {} burns for {} hit points.", object.name, BURN_DAMAGE)
+          };
+          game.log.add(message, colors::ORANGE);
+          object.take_damage(BURN_DAMAGE, Killer::Environment("fire"), game);
+        }
+        StatusKind::Regenerating => {
+          object.heal(REGENERATION_AMOUNT, game);
+        }
+        _ => {}
+      }
+    }
+
+    if !object.alive {
+      object.statuses.clear();
+      continue;
+    }
+    for status in object.statuses.iter_mut() {
+      status.turns -= 1;
+    }
+    for status in object.statuses.iter().filter(|status| status.turns <= 0) {
+      if id == PLAYER {
+        game.log.add(format!("You are no longer {}.", status.kind.name()), colors::WHITE);
+      } else {
+        game.log.add(format!("The {} is no longer {}!", object.name, status.kind.name()), colors::RED);
+      }
+      game.events.push(Event::StatusExpired { name: object.name.clone(), status: status.kind });
+    }
+    object.statuses.retain(|status| status.turns > 0);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::{monster, open_level};
+
+  #[test]
+  fn statuses_only_ever_get_longer() {
+    let mut game = open_level().game;
+    let mut orc = monster(0, 0, 3);
+    apply_status(&mut orc, StatusKind::Slowed, 5, &mut game);
+    apply_status(&mut orc, StatusKind::Slowed, 3, &mut game);
+    assert_eq!(orc.status_turns(StatusKind::Slowed), Some(5));
+    apply_status(&mut orc, StatusKind::Slowed, 8, &mut game);
+    assert_eq!(orc.status_turns(StatusKind::Slowed), Some(8));
+    // only the first one is news
+    let applied = Event::StatusApplied { name: "orc-1".into(), status: StatusKind::Slowed };
+    assert_eq!(game.events.iter().filter(|&event| *event == applied).count(), 1);
+  }
+
+  #[test]
+  fn the_dead_and_no_turns_get_no_status() {
+    let mut game = open_level().game;
+    let mut orc = monster(0, 0, 3);
+    apply_status(&mut orc, StatusKind::Hasted, 0, &mut game);
+    assert!(orc.statuses.is_empty());
+    orc.alive = false;
+    apply_status(&mut orc, StatusKind::Hasted, 5, &mut game);
+    assert!(orc.statuses.is_empty());
+    assert!(game.events.is_empty());
+  }
+
+  #[test]
+  fn poison_hurts_every_turn_until_it_wears_off() {
+    let mut state = open_level();
+    state.objects.push(monster(12, 10, 3));
+    apply_status(&mut state.objects[1], StatusKind::Poisoned, 2, &mut state.game);
+    tick_statuses(&mut state.objects, &mut state.game);
+    assert_eq!(state.objects[1].fighter.unwrap().hp, 10 - POISON_DAMAGE);
+    assert_eq!(state.objects[1].status_turns(StatusKind::Poisoned), Some(1));
+    tick_statuses(&mut state.objects, &mut state.game);
+    assert_eq!(state.objects[1].fighter.unwrap().hp, 10 - 2 * POISON_DAMAGE);
+    assert!(state.objects[1].statuses.is_empty());
+    assert!(state.game.events.contains(&Event::StatusExpired { name: "orc-1".into(), status: StatusKind::Poisoned }));
+  }
+
+  #[test]
+  fn regeneration_heals_up_to_full_health() {
+    let mut state = open_level();
+    state.objects.push(monster(12, 10, 3));
+    state.objects[1].fighter.as_mut().unwrap().hp = 9;
+    apply_status(&mut state.objects[1], StatusKind::Regenerating, 5, &mut state.game);
+    tick_statuses(&mut state.objects, &mut state.game);
+    assert_eq!(state.objects[1].fighter.unwrap().hp, 10);
+  }
+
+  #[test]
+  fn burning_to_death_gives_nobody_xp() {
+    let mut state = open_level();
+    state.objects.push(monster(12, 10, 3));
+    state.objects[1].fighter.as_mut().unwrap().hp = BURN_DAMAGE;
+    let xp = state.objects[PLAYER].fighter.unwrap().xp;
+    apply_status(&mut state.objects[1], StatusKind::Burning, 5, &mut state.game);
+    tick_statuses(&mut state.objects, &mut state.game);
+    assert!(!state.objects[1].alive);
+    assert!(state.objects[1].statuses.is_empty());
+    assert_eq!(state.objects[PLAYER].fighter.unwrap().xp, xp);
+  }
+}
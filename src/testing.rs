@@ -0,0 +1,46 @@
+// Fixtures shared by the unit tests here and the integration tests in tests/.
+// It can't be cfg(test), since the integration tests link the library as it
+// is built without it.
+
+use ai::Ai;
+use colors;
+use game::{new_game, GameState, PLAYER};
+use map::{Tile, MAP_HEIGHT, MAP_WIDTH};
+use object::{DeathCallback, Fighter, Object};
+
+// A level with nothing on it but open floor inside a wall, and the player at
+// (10, 10).
+pub fn open_level() -> GameState {
+  let mut state = new_game(1, Default::default());
+  state.objects.truncate(PLAYER + 1);
+  for x in 0..MAP_WIDTH {
+    for y in 0..MAP_HEIGHT {
+      let edge = x == 0 || y == 0 || x == MAP_WIDTH - 1 || y == MAP_HEIGHT - 1;
+      state.game.map[x as usize][y as usize] = if edge { Tile::wall() } else { Tile::empty() };
+    }
+  }
+  state.objects[PLAYER].set_pos(10, 10);
+  state.recompute_fov();
+  state
+}
+
+// A 10 hp orc that never runs away.
+pub fn monster(x: i32, y: i32, power: i32) -> Object {
+  let mut monster = Object::new(x, y, 'o', "orc-1", colors::DESATURATED_GREEN, true);
+  monster.alive = true;
+  monster.ai = Some(Ai::Basic);
+  monster.fighter = Some(Fighter {
+    max_hp: 10,
+    hp: 10,
+    defense: 0,
+    power,
+    xp: 35,
+    on_death: DeathCallback::Monster,
+    on_hit: None,
+    speed: 100,
+    vision: 12,
+    flee_below: 0,
+    ranged: None,
+  });
+  monster
+}
@@ -0,0 +1,81 @@
+extern crate the_glass_oak;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+
+use the_glass_oak::definitions::Definitions;
+use the_glass_oak::game::new_game;
+use the_glass_oak::replay::Replay;
+use the_glass_oak::{Action, GameState};
+
+const SEED: u64 = 42;
+const STEPS: usize = 300;
+
+fn replay_path(name: &str) -> PathBuf {
+  env::temp_dir().join(format!("the_glass_oak-{}-{}", name, process::id()))
+}
+
+// Wanders about in a fixed pattern, picking things up and taking the stairs
+// when it happens to be on them.
+fn play(state: &mut GameState) {
+  let moves = [Action::Move(1, 0), Action::Move(1, 1), Action::Move(0, 1), Action::Move(-1, 1),
+    Action::Move(-1, 0), Action::Move(-1, -1), Action::Move(0, -1), Action::Move(1, -1)];
+  for step in 0..STEPS {
+    let action = match step % 5 {
+      0 => Action::PickUp,
+      1 => Action::Descend,
+      _ => moves[(step / 3) % moves.len()],
+    };
+    state.step(action);
+  }
+}
+
+#[test]
+fn a_saved_replay_plays_back_to_the_same_state() {
+  let mut state = new_game(SEED, Definitions::default());
+  play(&mut state);
+
+  let path = replay_path("replay");
+  let path = path.to_str().unwrap();
+  state.game.replay.as_ref().unwrap().write(path).unwrap();
+  let replay = Replay::load(path);
+  fs::remove_file(path).unwrap();
+  let replay = replay.unwrap();
+  assert_eq!(replay.seed, SEED);
+  assert_eq!(replay.actions.len(), STEPS);
+
+  let played_back = replay.simulate(&Definitions::default()).unwrap();
+  assert_eq!(played_back.player().pos(), state.player().pos());
+  assert_eq!(played_back.player().fighter.map(|f| f.hp), state.player().fighter.map(|f| f.hp));
+  assert_eq!(played_back.game.dungeon_level, state.game.dungeon_level);
+  assert_eq!(played_back.game.turn, state.game.turn);
+}
+
+#[test]
+fn replays_with_impossible_actions_are_refused() {
+  let path = replay_path("bad-action");
+  let path = path.to_str().unwrap();
+  let mut replay = Replay::new(SEED, &Definitions::default());
+  replay.actions.push(Action::Wait);
+  replay.write(path).unwrap();
+  {
+    let mut file = fs::OpenOptions::new().append(true).open(path).unwrap();
+    writeln!(file, "{{\"Move\":[5,0]}}").unwrap();
+  }
+  let loaded = Replay::load(path);
+  fs::remove_file(path).unwrap();
+  assert!(loaded.unwrap_err().to_string().starts_with("line 3:"));
+}
+
+#[test]
+fn replays_are_refused_with_other_definitions() {
+  let defs = Definitions::default();
+  let replay = Replay::new(SEED, &defs);
+  let mut other = defs.clone();
+  other.monsters[0].fighter.power += 1;
+  assert!(replay.check_definitions(&defs).is_ok());
+  assert!(replay.simulate(&other).is_err());
+}
@@ -0,0 +1,115 @@
+extern crate the_glass_oak;
+
+use the_glass_oak::colors;
+use the_glass_oak::game::PLAYER;
+use the_glass_oak::item::Item;
+use the_glass_oak::object::{Equipment, Object, Slot, Stairs};
+use the_glass_oak::testing::{monster, open_level};
+use the_glass_oak::{Action, Event};
+
+#[test]
+fn moving_reports_where_the_player_went() {
+  let mut state = open_level();
+  let events = state.step(Action::Move(1, 0));
+  assert!(events.contains(&Event::PlayerMoved { from: (10, 10), to: (11, 10) }));
+  assert!(events.contains(&Event::TookTurn));
+  assert_eq!(state.player().pos(), (11, 10));
+}
+
+#[test]
+fn moves_of_more_than_one_tile_are_refused() {
+  let mut state = open_level();
+  assert!(state.step(Action::Move(3, 0)).is_empty());
+  assert!(state.step(Action::Move(500, 0)).is_empty());
+  assert!(state.step(Action::Move(0, -2)).is_empty());
+  assert_eq!(state.player().pos(), (10, 10));
+}
+
+#[test]
+fn walls_block_moves() {
+  let mut state = open_level();
+  state.objects[PLAYER].set_pos(1, 1);
+  let events = state.step(Action::Move(-1, 0));
+  assert!(!events.iter().any(|event| matches!(*event, Event::PlayerMoved { .. })));
+  assert_eq!(state.player().pos(), (1, 1));
+}
+
+#[test]
+fn bumping_a_monster_attacks_it() {
+  let mut state = open_level();
+  state.objects.push(monster(11, 10, 0));
+  let events = state.step(Action::Move(1, 0));
+  let power = state.player().power(&state.game);
+  assert!(events.contains(&Event::Attacked { attacker: "player".into(), target: "orc-1".into(), damage: power }));
+  assert_eq!(state.player().pos(), (10, 10));
+}
+
+#[test]
+fn picking_up_takes_the_item_underfoot() {
+  let mut state = open_level();
+  let mut potion = Object::new(10, 10, '!', "healing potion", colors::VIOLET, false);
+  potion.item = Some(Item::Heal);
+  state.objects.push(potion);
+  let events = state.step(Action::PickUp);
+  assert!(events.contains(&Event::ItemPickedUp { name: "healing potion".into() }));
+  assert_eq!(state.game.inventory.len(), 1);
+  assert_eq!(state.objects.len(), 1);
+}
+
+#[test]
+fn only_items_that_get_used_take_time() {
+  let mut state = open_level();
+  let mut potion = Object::new(10, 10, '!', "healing potion", colors::VIOLET, false);
+  potion.item = Some(Item::Heal);
+  state.game.inventory.push(potion);
+  // at full health there's nothing to heal, so it isn't drunk
+  let events = state.step(Action::UseItem { inventory_id: 0, target: None });
+  assert!(!events.contains(&Event::TookTurn));
+  state.objects[PLAYER].fighter.as_mut().unwrap().hp = 1;
+  let events = state.step(Action::UseItem { inventory_id: 0, target: None });
+  assert!(events.contains(&Event::TookTurn));
+  assert!(state.game.inventory.is_empty());
+}
+
+#[test]
+fn taking_off_a_ring_of_vitality_takes_its_hp_with_it() {
+  let mut state = open_level();
+  let mut ring = Object::new(10, 10, '=', "ring of vitality", colors::YELLOW, false);
+  ring.item = Some(Item::Ring);
+  ring.equipment = Some(Equipment { slot: Slot::Ring, equipped: false, power_bonus: 0, defense_bonus: 0, max_hp_bonus: 20 });
+  state.game.inventory.push(ring);
+  state.step(Action::UseItem { inventory_id: 0, target: None });
+  let max_hp = state.player().max_hp(&state.game);
+  state.objects[PLAYER].fighter.as_mut().unwrap().hp = max_hp;
+  state.step(Action::UseItem { inventory_id: 0, target: None });
+  assert_eq!(state.player().fighter.unwrap().hp, max_hp - 20);
+  assert_eq!(state.player().max_hp(&state.game), max_hp - 20);
+}
+
+#[test]
+fn descending_needs_stairs_underfoot() {
+  let mut state = open_level();
+  assert!(state.step(Action::Descend).is_empty());
+  assert_eq!(state.game.dungeon_level, 1);
+
+  let mut stairs = Object::new(10, 10, '>', "down stairs", colors::WHITE, false);
+  stairs.stairs = Some(Stairs::Down);
+  state.objects.push(stairs);
+  let events = state.step(Action::Descend);
+  assert!(events.contains(&Event::Descended { dungeon_level: 2 }));
+  assert_eq!(state.game.dungeon_level, 2);
+}
+
+#[test]
+fn the_player_can_be_killed() {
+  let mut state = open_level();
+  state.objects[PLAYER].fighter.as_mut().unwrap().hp = 1;
+  state.objects.push(monster(11, 10, 50));
+  let events = state.step(Action::Wait);
+  assert!(events.contains(&Event::PlayerDied));
+  assert!(!state.player().alive);
+  assert_eq!(state.game.cause_of_death, Some("orc".into()));
+  assert_eq!(state.game.killed_by, Some("orc-1".into()));
+  // nothing happens once the player is dead
+  assert!(state.step(Action::Wait).is_empty());
+}